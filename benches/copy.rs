@@ -0,0 +1,80 @@
+//! Compares the copy engine against the 1 KiB read/seek/lock loop that transfers used to run.
+//!
+//! Run: cargo bench --features backend --bench copy
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pitou_core::backend::transfer::engine::{self, CopyStrategy, Progress};
+
+const SIZES: [u64; 2] = [4 << 20, 64 << 20];
+
+fn bench_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join("pitou-copy-bench");
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn make_source(dir: &Path, len: u64) -> PathBuf {
+    let path = dir.join(format!("src-{len}"));
+    let mut file = File::create(&path).unwrap();
+    let block: Vec<u8> = (0..1 << 16).map(|v| (v * 31 % 251) as u8).collect();
+    let mut written = 0;
+    while written < len {
+        file.write_all(&block).unwrap();
+        written += block.len() as u64;
+    }
+    path
+}
+
+fn legacy_copy(src: &Path, dst: &Path, state: &Mutex<u64>) {
+    let mut src_file = File::open(src).unwrap();
+    let mut dst_file = File::create(dst).unwrap();
+    let mut seek_ptr = 0;
+    let mut buffer = vec![0; 1024];
+    while seek_ptr < src_file.metadata().unwrap().len() {
+        let cnt = src_file.read(&mut buffer).unwrap();
+        dst_file.write_all(&buffer[..cnt]).unwrap();
+        seek_ptr += cnt as u64;
+        src_file.seek(SeekFrom::Start(seek_ptr)).unwrap();
+        *state.lock().unwrap() += cnt as u64;
+    }
+}
+
+fn engine_copy(src: &Path, dst: &Path, strategy: CopyStrategy, state: &Mutex<u64>) {
+    let src_file = File::open(src).unwrap();
+    let dst_file = File::create(dst).unwrap();
+    let mut progress = Progress::new(|cnt| *state.lock().unwrap() += cnt);
+    engine::copy_contents(&src_file, &dst_file, strategy, &mut progress).unwrap();
+}
+
+fn copy_throughput(c: &mut Criterion) {
+    let dir = bench_dir();
+    let dst = dir.join("dst");
+    let state = Mutex::new(0);
+    let mut group = c.benchmark_group("copy");
+    group.sample_size(10);
+    for len in SIZES {
+        let src = make_source(&dir, len);
+        group.throughput(Throughput::Bytes(len));
+        group.bench_with_input(BenchmarkId::new("legacy_1k", len), &src, |b, src| {
+            b.iter(|| legacy_copy(src, &dst, &state))
+        });
+        group.bench_with_input(BenchmarkId::new("buffered", len), &src, |b, src| {
+            b.iter(|| engine_copy(src, &dst, CopyStrategy::Buffered, &state))
+        });
+        group.bench_with_input(BenchmarkId::new("auto", len), &src, |b, src| {
+            b.iter(|| engine_copy(src, &dst, CopyStrategy::Auto, &state))
+        });
+    }
+    group.finish();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+criterion_group!(benches, copy_throughput);
+criterion_main!(benches);
@@ -0,0 +1,286 @@
+//! Comparison of two folders, and syncs that bring one in line with the other. The copies of a sync go through a
+//! single transfer session, so they are followed like any paste.
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fs::Metadata,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    backend::{deletion, transfer},
+    msg::{CompareEntry, CompareMethod, CompareStatus, SyncMode, SyncStarted, TransferOptions},
+    PitouFile, PitouFilePath,
+};
+
+/// Modification times closer than this are taken to be the same, as FAT drives only keep them to two seconds.
+const TIME_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// Compares what the folders `left` and `right` hold, recursively. `None` if either of them is not a folder.
+pub async fn compare(
+    left: PitouFilePath,
+    right: PitouFilePath,
+    method: CompareMethod,
+) -> Option<Vec<CompareEntry>> {
+    tokio::task::spawn_blocking(move || compare_dirs(&left.path, &right.path, method))
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Compares `left` and `right` and starts what `mode` calls for to bring `right` in line with `left`, or both in line
+/// with each other for two-way syncs. Copies keep the modification times of their sources, whatever `options` says,
+/// so that the next comparison finds them identical. `None` if either of them is not a folder.
+pub async fn sync(
+    left: PitouFilePath,
+    right: PitouFilePath,
+    mode: SyncMode,
+    method: CompareMethod,
+    mut options: TransferOptions,
+) -> Option<SyncStarted> {
+    options.preserve.times = true;
+    let (left, right) = (left.path, right.path);
+    tokio::task::spawn_blocking(move || {
+        let entries = compare_dirs(&left, &right, method)?;
+        let plan = plan(entries, mode, &left, &right);
+        Some(SyncStarted {
+            transfer: (!plan.copies.is_empty()).then(|| transfer::copy_each(plan.copies, options)),
+            deletion: (!plan.trash.is_empty()).then(|| deletion::trash(plan.trash)),
+            conflicts: plan.conflicts,
+        })
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+fn compare_dirs(left: &Path, right: &Path, method: CompareMethod) -> Option<Vec<CompareEntry>> {
+    if !left.is_dir() || !right.is_dir() {
+        return None;
+    }
+    let mut entries = Vec::new();
+    compare_within(left, right, method, &mut entries);
+    Some(entries)
+}
+
+/// The items of `dir` by name. Links are not followed.
+fn children(dir: &Path) -> BTreeMap<OsString, (PathBuf, Metadata)> {
+    std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some((entry.file_name(), (entry.path(), metadata)))
+        })
+        .collect()
+}
+
+fn compare_within(
+    left: &Path,
+    right: &Path,
+    method: CompareMethod,
+    entries: &mut Vec<CompareEntry>,
+) {
+    let mut rights = children(right);
+    let mut pairs = children(left)
+        .into_iter()
+        .map(|(name, left)| (name.clone(), Some(left), rights.remove(&name)))
+        .collect::<Vec<_>>();
+    pairs.extend(
+        rights
+            .into_iter()
+            .map(|(name, right)| (name, None, Some(right))),
+    );
+    pairs.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    for (_, left, right) in pairs {
+        if let (Some((l, lm)), Some((r, rm))) = (&left, &right) {
+            if lm.is_dir() && rm.is_dir() {
+                compare_within(l, r, method, entries);
+                continue;
+            }
+        }
+        let status = status(left.as_ref(), right.as_ref(), method);
+        let file = |(path, metadata)| PitouFile::new(path, metadata);
+        entries.push(CompareEntry {
+            status,
+            left: left.map(file),
+            right: right.map(file),
+        });
+    }
+}
+
+fn status(
+    left: Option<&(PathBuf, Metadata)>,
+    right: Option<&(PathBuf, Metadata)>,
+    method: CompareMethod,
+) -> CompareStatus {
+    let ((l, lm), (r, rm)) = match (left, right) {
+        (Some(left), Some(right)) => (left, right),
+        (Some(_), None) => return CompareStatus::LeftOnly,
+        _ => return CompareStatus::RightOnly,
+    };
+    if lm.is_dir() != rm.is_dir() {
+        return CompareStatus::KindMismatch;
+    }
+    let modified = |v: &Metadata| v.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    let (lt, rt) = (modified(lm), modified(rm));
+    let same_time = lt.max(rt).duration_since(lt.min(rt)).unwrap_or_default() < TIME_TOLERANCE;
+    let same = lm.len() == rm.len()
+        && match method {
+            CompareMethod::SizeAndTime => same_time,
+            CompareMethod::Content => transfer::verify::checksum(l)
+                .is_ok_and(|v| transfer::verify::checksum(r).is_ok_and(|u| u == v)),
+        };
+    match (same, same_time) {
+        (true, _) => CompareStatus::Identical,
+        (false, true) => CompareStatus::Differs,
+        (false, false) if lt > rt => CompareStatus::NewerOnLeft,
+        (false, false) => CompareStatus::NewerOnRight,
+    }
+}
+
+#[derive(Default)]
+struct SyncPlan {
+    /// items to copy, each with the folder it goes to
+    copies: Vec<(PathBuf, PathBuf)>,
+    trash: Vec<PitouFile>,
+    conflicts: Vec<CompareEntry>,
+}
+
+/// What a sync of `mode` does about each of `entries`, a comparison of `left` with `right`. Entries that differ with
+/// no side newer than the other, or that are folders on one side only, are left alone as conflicts.
+fn plan(entries: Vec<CompareEntry>, mode: SyncMode, left: &Path, right: &Path) -> SyncPlan {
+    // the folder of the other side that an item goes to
+    let across = |path: &Path, from: &Path, to: &Path| {
+        let relative = path.strip_prefix(from).unwrap_or(path);
+        to.join(relative).parent().unwrap_or(to).to_path_buf()
+    };
+    let mut plan = SyncPlan::default();
+    for entry in entries {
+        let to_right = |v: &PitouFile| (v.path.path.clone(), across(&v.path.path, left, right));
+        let to_left = |v: &PitouFile| (v.path.path.clone(), across(&v.path.path, right, left));
+        match (entry.status, mode) {
+            (CompareStatus::Identical, _) => (),
+            (CompareStatus::LeftOnly | CompareStatus::NewerOnLeft, _)
+            | (CompareStatus::NewerOnRight | CompareStatus::Differs, SyncMode::Mirror) => {
+                plan.copies.extend(entry.left.as_ref().map(to_right))
+            }
+            (CompareStatus::RightOnly, SyncMode::Mirror) => plan.trash.extend(entry.right),
+            (CompareStatus::RightOnly | CompareStatus::NewerOnRight, SyncMode::TwoWay) => {
+                plan.copies.extend(entry.right.as_ref().map(to_left))
+            }
+            (CompareStatus::RightOnly | CompareStatus::NewerOnRight, SyncMode::Update) => (),
+            (CompareStatus::Differs | CompareStatus::KindMismatch, _) => plan.conflicts.push(entry),
+        }
+    }
+    plan
+}
+
+#[cfg(test)]
+mod test_mod {
+    use super::*;
+    use crate::{backend::testing, msg::TransferMsg};
+
+    fn statuses(entries: &[CompareEntry], root: &Path) -> Vec<(PathBuf, CompareStatus)> {
+        entries
+            .iter()
+            .map(|v| {
+                let path = &v.left.as_ref().or(v.right.as_ref()).unwrap().path.path;
+                let path = path.strip_prefix(root).unwrap_or(path);
+                let path = path.components().skip(1).collect::<PathBuf>();
+                (path, v.status)
+            })
+            .collect()
+    }
+
+    fn wait_for(id: crate::msg::TransferSessionID) -> TransferMsg {
+        testing::wait_for(
+            || transfer::get_session_with_id(id).unwrap(),
+            TransferMsg::is_terminated,
+        )
+    }
+
+    #[test]
+    fn test_compare_and_sync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let (left, right) = (root.join("laptop"), root.join("drive"));
+        std::fs::create_dir_all(left.join("src").join("new")).unwrap();
+        std::fs::create_dir_all(right.join("src")).unwrap();
+        std::fs::create_dir_all(right.join("build")).unwrap();
+        let old = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+        let older = filetime::FileTime::from_unix_time(1_500_000_000, 0);
+        let write = |path: PathBuf, content: &[u8], time| {
+            std::fs::write(&path, content).unwrap();
+            filetime::set_file_mtime(&path, time).unwrap();
+        };
+        write(left.join("same.txt"), b"same", old);
+        write(right.join("same.txt"), b"same", old);
+        write(left.join("src").join("main.rs"), b"edited", old);
+        write(right.join("src").join("main.rs"), b"first", older);
+        write(left.join("src").join("new").join("mod.rs"), b"mod", old);
+        write(left.join("notes.txt"), b"laptop", older);
+        write(right.join("notes.txt"), b"drive", older);
+        write(right.join("build").join("out"), b"out", old);
+        write(right.join("todo.txt"), b"todo", old);
+        write(left.join("build"), b"not a folder", old);
+
+        let entries = compare_dirs(&left, &right, CompareMethod::SizeAndTime).unwrap();
+        let expected = [
+            ("build", CompareStatus::KindMismatch),
+            ("notes.txt", CompareStatus::Differs),
+            ("same.txt", CompareStatus::Identical),
+            ("src/main.rs", CompareStatus::NewerOnLeft),
+            ("src/new", CompareStatus::LeftOnly),
+            ("todo.txt", CompareStatus::RightOnly),
+        ];
+        let expected = expected
+            .into_iter()
+            .map(|(path, status)| (PathBuf::from(path), status))
+            .collect::<Vec<_>>();
+        assert_eq!(statuses(&entries, root), expected);
+        let by_content = compare_dirs(&left, &right, CompareMethod::Content).unwrap();
+        assert_eq!(by_content[1].status, CompareStatus::Differs);
+        assert_eq!(by_content[2].status, CompareStatus::Identical);
+
+        let mirror = plan(entries, SyncMode::Mirror, &left, &right);
+        assert_eq!(mirror.copies.len(), 3);
+        assert_eq!(mirror.trash.len(), 1);
+        assert_eq!(mirror.conflicts.len(), 1);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (l, r) = (
+            PitouFilePath::from_pathbuf(left.clone()),
+            PitouFilePath::from_pathbuf(right.clone()),
+        );
+        let started = runtime
+            .block_on(sync(
+                l,
+                r,
+                SyncMode::TwoWay,
+                CompareMethod::SizeAndTime,
+                TransferOptions::default(),
+            ))
+            .unwrap();
+        assert!(started.deletion.is_none());
+        assert_eq!(started.conflicts.len(), 2);
+        wait_for(started.transfer.unwrap());
+
+        assert_eq!(
+            std::fs::read(right.join("src").join("main.rs")).unwrap(),
+            b"edited"
+        );
+        assert!(right.join("src").join("new").join("mod.rs").is_file());
+        assert!(left.join("todo.txt").is_file());
+        let entries = compare_dirs(&left, &right, CompareMethod::SizeAndTime).unwrap();
+        let pending = entries
+            .iter()
+            .filter(|v| v.status != CompareStatus::Identical)
+            .count();
+        assert_eq!(pending, 2);
+    }
+}
@@ -0,0 +1,399 @@
+//! Delete sessions. Like transfers, every call to delete runs on its own thread and is followed through an id.
+//!
+//! Items go to the trash unless they are deleted permanently, which needs a [`DeleteToken`] obtained for the very same
+//! items beforehand, so that no code path can skip the confirmation.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use rand::RngCore;
+
+use super::trash_ops;
+
+use crate::{
+    collections::Registry,
+    msg::{
+        DeleteMsg, DeleteToken, PermanentDeleteOptions, TransferError, TransferOutcome,
+        TransferSessionID, TransferSize, TransferState,
+    },
+    PitouFile, PitouFilePath,
+};
+
+/// How long a confirmation stays valid.
+const TOKEN_LIFETIME: Duration = Duration::from_secs(120);
+const SHRED_BUFFER_SIZE: usize = 1024 * 1024;
+
+#[derive(Clone, Copy)]
+enum DeleteMode {
+    Trash,
+    Permanent(PermanentDeleteOptions),
+}
+
+struct DeleteConfig {
+    id: TransferSessionID,
+    state: Mutex<TransferState>,
+    started: Mutex<Instant>,
+    items_total: u64,
+    /// the items being deleted
+    items: Vec<PathBuf>,
+    items_done: Mutex<u64>,
+    failures: Mutex<Vec<TransferError>>,
+    outcome: Mutex<Option<TransferOutcome>>,
+}
+
+impl DeleteConfig {
+    fn is_ongoing(&self) -> bool {
+        !self.state.lock().unwrap().is_terminted()
+    }
+
+    fn append_total(&self, val: u64) {
+        if let TransferState::Initializing(total) = &mut *self.state.lock().unwrap() {
+            *total += val
+        }
+    }
+
+    fn start_now(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let TransferState::Initializing(total) = *state {
+            *state = TransferState::Active(TransferSize { total, current: 0 })
+        }
+        *self.started.lock().unwrap() = Instant::now();
+    }
+
+    fn advance(&self, val: u64) {
+        if let TransferState::Active(size) = &mut *self.state.lock().unwrap() {
+            size.current += val
+        }
+    }
+
+    fn finish_item(&self) {
+        *self.items_done.lock().unwrap() += 1
+    }
+
+    fn record_failure(&self, path: &Path, message: String) {
+        let path = PitouFilePath::from_pathbuf(path.to_path_buf());
+        self.failures
+            .lock()
+            .unwrap()
+            .push(TransferError { path, message });
+    }
+
+    fn terminate_now(&self, failed: usize) {
+        let errors = self.failures.lock().unwrap().len();
+        let outcome = TransferOutcome::judge(errors, failed, self.items_total as usize);
+        *self.outcome.lock().unwrap() = Some(outcome);
+        let mut state = self.state.lock().unwrap();
+        if let TransferState::Active(size) = *state {
+            *state = TransferState::Terminated(size)
+        }
+    }
+
+    fn read(&self) -> DeleteMsg {
+        DeleteMsg {
+            id: self.id,
+            state: *self.state.lock().unwrap(),
+            time_elapsed: self.started.lock().unwrap().elapsed(),
+            items_done: *self.items_done.lock().unwrap(),
+            items_total: self.items_total,
+            failures: self.failures.lock().unwrap().clone(),
+            outcome: *self.outcome.lock().unwrap(),
+        }
+    }
+}
+
+type DeleteSessions = Mutex<Registry<Arc<DeleteConfig>>>;
+static DELETE_SESSIONS: OnceLock<DeleteSessions> = OnceLock::new();
+
+fn get_sessions() -> &'static DeleteSessions {
+    DELETE_SESSIONS.get_or_init(|| Mutex::new(Registry::new()))
+}
+
+fn add_new_session(items: &[PathBuf]) -> Arc<DeleteConfig> {
+    let mut sessions = get_sessions().lock().unwrap();
+    let key = sessions.insert_with(|key| {
+        Arc::new(DeleteConfig {
+            id: key.into(),
+            state: Mutex::new(TransferState::Initializing(0)),
+            started: Mutex::new(Instant::now()),
+            items_total: items.len() as u64,
+            items: items.to_vec(),
+            items_done: Mutex::new(0),
+            failures: Mutex::new(Vec::new()),
+            outcome: Mutex::new(None),
+        })
+    });
+    sessions.get(key).unwrap().clone()
+}
+
+/// Starts moving `items` to the trash and returns the id of the session doing it.
+pub(crate) fn trash(items: Vec<PitouFile>) -> TransferSessionID {
+    let items = items.into_iter().map(|v| v.path.path).collect();
+    begin(items, DeleteMode::Trash)
+}
+
+type PendingConfirmations = Mutex<HashMap<DeleteToken, (Vec<PathBuf>, Instant)>>;
+static CONFIRMATIONS: OnceLock<PendingConfirmations> = OnceLock::new();
+
+fn get_confirmations() -> &'static PendingConfirmations {
+    CONFIRMATIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Issues the token that [`permanent_delete`] requires to delete `items`. It should be requested only once the user
+/// has confirmed, and expires after two minutes.
+pub fn confirm_permanent_delete(items: Vec<PitouFile>) -> DeleteToken {
+    let items = items.into_iter().map(|v| v.path.path).collect();
+    let mut confirmations = get_confirmations().lock().unwrap();
+    confirmations.retain(|_, (_, issued)| issued.elapsed() < TOKEN_LIFETIME);
+    let token = DeleteToken::new(rand::thread_rng().next_u64());
+    confirmations.insert(token, (items, Instant::now()));
+    token
+}
+
+/// Deletes the items confirmed with `token` without going through the trash. The token is used up.
+///
+/// Returns `None` if the token is unknown, already used or expired.
+pub fn permanent_delete(
+    token: DeleteToken,
+    options: PermanentDeleteOptions,
+) -> Option<TransferSessionID> {
+    let (items, issued) = get_confirmations().lock().unwrap().remove(&token)?;
+    if issued.elapsed() >= TOKEN_LIFETIME {
+        return None;
+    }
+    Some(begin(items, DeleteMode::Permanent(options)))
+}
+
+fn begin(items: Vec<PathBuf>, mode: DeleteMode) -> TransferSessionID {
+    let config = add_new_session(&items);
+    let id = config.id;
+    std::thread::spawn(move || {
+        let sizes = items
+            .iter()
+            .map(|item| match mode {
+                DeleteMode::Trash => size_of(item),
+                DeleteMode::Permanent(options) => work_of(item, options.shred_passes),
+            })
+            .collect::<Vec<_>>();
+        config.append_total(sizes.iter().sum());
+        config.start_now();
+        let mut failed = 0;
+        for (item, size) in items.into_iter().zip(sizes) {
+            let deleted = match mode {
+                DeleteMode::Trash => {
+                    let mut reported = 0;
+                    let res = trash_ops::delete(&item, &mut |cnt| {
+                        reported += cnt;
+                        config.advance(cnt)
+                    });
+                    // the system trash reports nothing, and an item that fails part way is done with all the same
+                    config.advance(size.saturating_sub(reported));
+                    res.map_err(|e| config.record_failure(&item, e.to_string()))
+                        .is_ok()
+                }
+                DeleteMode::Permanent(options) => remove(&config, &item, options),
+            };
+            if deleted {
+                config.finish_item()
+            } else {
+                failed += 1
+            }
+        }
+        if let DeleteMode::Trash = mode {
+            super::fs_ops::apply_trash_retention();
+        }
+        config.terminate_now(failed);
+    });
+    id
+}
+
+/// Permanently removes `path` and everything under it, never following links. Failures are recorded and the rest of
+/// the tree is still attempted. Returns whether `path` is gone.
+fn remove(config: &DeleteConfig, path: &Path, options: PermanentDeleteOptions) -> bool {
+    let res = match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => {
+            let mut all_removed = true;
+            match std::fs::read_dir(path) {
+                Ok(rd) => {
+                    for entry in rd {
+                        all_removed &= match entry {
+                            Ok(entry) => remove(config, &entry.path(), options),
+                            Err(e) => {
+                                config.record_failure(path, e.to_string());
+                                false
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    config.record_failure(path, e.to_string());
+                    return false;
+                }
+            }
+            if !all_removed {
+                // the failures below already explain why this folder stays
+                return false;
+            }
+            std::fs::remove_dir(path)
+        }
+        Ok(metadata) if metadata.is_file() && options.shred_passes > 0 => {
+            shred(config, path, metadata.len(), options.shred_passes)
+                .and_then(|_| std::fs::remove_file(path))
+        }
+        Ok(metadata) => std::fs::remove_file(path).map(|_| config.advance(metadata.len())),
+        Err(e) => Err(e),
+    };
+    res.map_err(|e| config.record_failure(path, e.to_string()))
+        .is_ok()
+}
+
+/// Overwrites the `len` bytes of the file at `path` with random data `passes` times, syncing after each pass.
+///
+/// This only destroys the old contents where the filesystem writes in place. Copy-on-write filesystems, snapshots and
+/// SSD wear levelling can all keep copies of the previous blocks out of reach.
+fn shred(config: &DeleteConfig, path: &Path, len: u64, passes: u32) -> io::Result<()> {
+    let mut file = File::options().write(true).open(path)?;
+    let mut buffer = vec![0; SHRED_BUFFER_SIZE];
+    let mut rng = rand::thread_rng();
+    for _ in 0..passes {
+        file.rewind()?;
+        let mut remaining = len;
+        while remaining > 0 {
+            let cnt = remaining.min(buffer.len() as u64) as usize;
+            rng.fill_bytes(&mut buffer[..cnt]);
+            file.write_all(&buffer[..cnt])?;
+            remaining -= cnt as u64;
+            config.advance(cnt as u64);
+        }
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+/// The progress [`remove`] makes on `path` when shredding files with `passes`: every byte of a file once per pass, and
+/// the length of any other item once.
+fn work_of(path: &Path, passes: u32) -> u64 {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if metadata.is_file() {
+        return metadata.len() * passes.max(1) as u64;
+    }
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    std::fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| work_of(&entry.path(), passes))
+        .sum()
+}
+
+/// Total size of the files under `path`, not following links. Unreadable parts count as empty.
+pub(crate) fn size_of(path: &Path) -> u64 {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    std::fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| size_of(&entry.path()))
+        .sum()
+}
+
+pub fn get_all_active_sessions() -> Vec<DeleteMsg> {
+    get_sessions()
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(_, v)| if v.is_ongoing() { Some(v.read()) } else { None })
+        .collect()
+}
+
+/// The state of the session with `id`. Terminated sessions stay available, as their final report, until
+/// [`clean_dead_sessions`] is called.
+pub fn get_session_with_id(id: TransferSessionID) -> Option<DeleteMsg> {
+    let sessions = get_sessions().lock().unwrap();
+    sessions.get(id.into()).map(|v| v.read())
+}
+
+/// Ongoing sessions deleting anything under `path`.
+pub(crate) fn sessions_within(path: &Path) -> Vec<TransferSessionID> {
+    get_sessions()
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, v)| v.is_ongoing() && v.items.iter().any(|u| u.starts_with(path)))
+        .map(|(_, v)| v.id)
+        .collect()
+}
+
+pub fn clean_dead_sessions() {
+    get_sessions().lock().unwrap().retain(|_, v| v.is_ongoing());
+}
+
+#[cfg(test)]
+mod test_mod {
+    use super::*;
+    use crate::backend::testing;
+
+    fn wait_for(id: TransferSessionID) -> DeleteMsg {
+        testing::wait_for(
+            || get_session_with_id(id).unwrap(),
+            |msg| msg.state.is_terminted(),
+        )
+    }
+
+    #[test]
+    fn test_permanent_delete() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        std::fs::create_dir_all(root.join("build").join("deps")).unwrap();
+        std::fs::write(root.join("build").join("deps").join("libfoo.a"), [1; 5000]).unwrap();
+        std::fs::write(root.join("build").join("keys.pem"), [2; 300]).unwrap();
+        // links are removed, not shredded, and count once
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("deps", root.join("build").join("latest")).unwrap();
+        let total = if cfg!(unix) { 10604 } else { 10600 };
+
+        let items = || {
+            vec![PitouFile::without_metadata(PitouFilePath::from_pathbuf(
+                root.join("build"),
+            ))]
+        };
+        let token = confirm_permanent_delete(items());
+        let options = PermanentDeleteOptions { shred_passes: 2 };
+        let msg = wait_for(permanent_delete(token, options).unwrap());
+        assert_eq!(msg.outcome, Some(TransferOutcome::Completed));
+        assert!(matches!(
+            msg.state,
+            TransferState::Terminated(size) if size.total == total && size.current == total
+        ));
+        assert!(!root.join("build").exists());
+        // a token only works once
+        assert!(permanent_delete(token, options).is_none());
+    }
+
+    #[test]
+    fn test_failed_delete_report() {
+        let tmp = tempfile::tempdir().unwrap();
+        let missing = tmp.path().join("missing");
+        let items = vec![PitouFile::without_metadata(PitouFilePath::from_pathbuf(
+            missing.clone(),
+        ))];
+        let msg = wait_for(trash(items));
+        assert_eq!(msg.outcome, Some(TransferOutcome::Failed));
+        assert_eq!((msg.items_done, msg.items_total), (0, 1));
+        assert!(msg.failures[0].path == PitouFilePath::from_pathbuf(missing));
+    }
+}
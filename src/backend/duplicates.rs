@@ -0,0 +1,439 @@
+//! Duplicate file scans. Each scan walks its folders on a thread of its own and is followed through an id, like disk
+//! usage scans.
+//!
+//! Candidates are narrowed in three passes so that most files are never read in full: files of the same length, then
+//! those whose first bytes hash the same, then those whose whole content does. A group is handed out as soon as its
+//! last pass is over.
+
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Instant, SystemTime},
+};
+
+use crate::{
+    backend::{deletion, search, transfer::verify, usage},
+    collections::Registry,
+    msg::{DuplicateGroup, DuplicateMsg, ScanState, TransferError, TransferSessionID},
+    PitouFile, PitouFileFilter, PitouFilePath,
+};
+
+/// Bytes hashed from the start of each candidate before whole files are.
+const HEAD_LEN: u64 = 64 * 1024;
+
+#[derive(Default)]
+struct Counters {
+    files: u64,
+    bytes_hashed: u64,
+    groups_found: u64,
+    wasted_bytes: u64,
+    errors: u64,
+}
+
+struct DuplicateConfig {
+    id: TransferSessionID,
+    started: Instant,
+    counters: Mutex<Counters>,
+    cancelled: AtomicBool,
+    state: Mutex<ScanState>,
+    /// confirmed groups not read yet
+    groups: Mutex<Vec<DuplicateGroup>>,
+}
+
+impl DuplicateConfig {
+    fn is_ongoing(&self) -> bool {
+        *self.state.lock().unwrap() == ScanState::Scanning
+    }
+
+    fn read(&self) -> DuplicateMsg {
+        let counters = self.counters.lock().unwrap();
+        DuplicateMsg {
+            id: self.id,
+            state: *self.state.lock().unwrap(),
+            time_elapsed: self.started.elapsed(),
+            files: counters.files,
+            bytes_hashed: counters.bytes_hashed,
+            groups_found: counters.groups_found,
+            wasted_bytes: counters.wasted_bytes,
+            errors: counters.errors,
+            groups: std::mem::take(&mut *self.groups.lock().unwrap()),
+        }
+    }
+}
+
+type DuplicateSessions = Mutex<Registry<Arc<DuplicateConfig>>>;
+static DUPLICATE_SESSIONS: OnceLock<DuplicateSessions> = OnceLock::new();
+
+fn get_sessions() -> &'static DuplicateSessions {
+    DUPLICATE_SESSIONS.get_or_init(|| Mutex::new(Registry::new()))
+}
+
+fn add_new_session() -> Arc<DuplicateConfig> {
+    let mut sessions = get_sessions().lock().unwrap();
+    let key = sessions.insert_with(|key| {
+        Arc::new(DuplicateConfig {
+            id: key.into(),
+            started: Instant::now(),
+            counters: Mutex::new(Counters::default()),
+            cancelled: AtomicBool::new(false),
+            state: Mutex::new(ScanState::Scanning),
+            groups: Mutex::new(Vec::new()),
+        })
+    });
+    sessions.get(key).unwrap().clone()
+}
+
+/// Starts looking for files with the same content under `dirs`, going down `depth` levels like a search. Only regular
+/// files that `filter` lets through are compared; empty files are left out, as are extra names of a hardlinked file.
+pub fn find_duplicates(
+    dirs: Vec<PitouFilePath>,
+    filter: PitouFileFilter,
+    depth: u8,
+) -> TransferSessionID {
+    let config = add_new_session();
+    let id = config.id;
+    std::thread::spawn(move || {
+        let dirs = dirs.into_iter().map(|v| v.path).collect::<Vec<_>>();
+        Scan { config: &config }.run(&dirs, filter, depth);
+        let mut state = config.state.lock().unwrap();
+        *state = match config.cancelled.load(Ordering::Relaxed) {
+            true => ScanState::Cancelled,
+            false => ScanState::Completed,
+        }
+    });
+    id
+}
+
+struct Candidate {
+    path: PathBuf,
+    modified: SystemTime,
+}
+
+struct Scan<'a> {
+    config: &'a DuplicateConfig,
+}
+
+impl Scan<'_> {
+    fn is_cancelled(&self) -> bool {
+        self.config.cancelled.load(Ordering::Relaxed)
+    }
+
+    fn run(&self, dirs: &[PathBuf], filter: PitouFileFilter, depth: u8) {
+        let mut by_size = HashMap::<u64, Vec<Candidate>>::new();
+        let mut seen = HashSet::new();
+        for dir in dirs {
+            let mut visit = |file: PitouFile| {
+                if self.is_cancelled() {
+                    return false;
+                }
+                if let Some((size, candidate)) = self.candidate(file.path.path, &mut seen) {
+                    by_size.entry(size).or_default().push(candidate);
+                }
+                true
+            };
+            if !search::walk(dir, depth, filter, &mut visit) {
+                return;
+            }
+        }
+
+        // largest first, as they waste the most space
+        let mut by_size = by_size
+            .into_iter()
+            .filter(|(_, v)| v.len() > 1)
+            .collect::<Vec<_>>();
+        by_size.sort_unstable_by_key(|(size, _)| Reverse(*size));
+        for (size, candidates) in by_size {
+            for candidates in self.split(candidates, size, true) {
+                if self.is_cancelled() {
+                    return;
+                }
+                // the heads of small files were all of them
+                let groups = match size <= HEAD_LEN {
+                    true => vec![candidates],
+                    false => self.split(candidates, size, false),
+                };
+                groups.into_iter().for_each(|v| self.confirm(size, v));
+            }
+        }
+    }
+
+    /// `path` as a candidate along with its length, unless it is not a regular file, is empty or is another name of a
+    /// file already met.
+    fn candidate(&self, path: PathBuf, seen: &mut HashSet<(u64, u64)>) -> Option<(u64, Candidate)> {
+        let metadata = match std::fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                self.config.counters.lock().unwrap().errors += 1;
+                return None;
+            }
+        };
+        if !metadata.is_file() || metadata.len() == 0 {
+            return None;
+        }
+        if let Some(id) = usage::hardlink_id(&metadata) {
+            if !seen.insert(id) {
+                return None;
+            }
+        }
+        self.config.counters.lock().unwrap().files += 1;
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        Some((metadata.len(), Candidate { path, modified }))
+    }
+
+    /// Splits `candidates`, which are `size` bytes long, by the hash of their head or of their whole content, keeping
+    /// the sets of more than one. Files that cannot be read are dropped.
+    fn split(&self, candidates: Vec<Candidate>, size: u64, head: bool) -> Vec<Vec<Candidate>> {
+        let (hash, len): (fn(&Path) -> io::Result<blake3::Hash>, u64) = match head {
+            true => (|v| verify::checksum_head(v, HEAD_LEN), size.min(HEAD_LEN)),
+            false => (verify::checksum, size),
+        };
+        let mut sets = HashMap::<blake3::Hash, Vec<Candidate>>::new();
+        for candidate in candidates {
+            if self.is_cancelled() {
+                return Vec::new();
+            }
+            match hash(&candidate.path) {
+                Ok(hash) => {
+                    self.config.counters.lock().unwrap().bytes_hashed += len;
+                    sets.entry(hash).or_default().push(candidate)
+                }
+                Err(_) => self.config.counters.lock().unwrap().errors += 1,
+            }
+        }
+        sets.into_values().filter(|v| v.len() > 1).collect()
+    }
+
+    fn confirm(&self, size: u64, mut files: Vec<Candidate>) {
+        files.sort_by_key(|v| Reverse(v.modified));
+        {
+            let mut counters = self.config.counters.lock().unwrap();
+            counters.groups_found += 1;
+            counters.wasted_bytes += size * (files.len() as u64 - 1);
+        }
+        let files = files
+            .into_iter()
+            .filter_map(|v| {
+                let metadata = std::fs::symlink_metadata(&v.path).ok()?;
+                Some(PitouFile::new(v.path, metadata))
+            })
+            .collect();
+        self.config
+            .groups
+            .lock()
+            .unwrap()
+            .push(DuplicateGroup { size, files });
+    }
+}
+
+/// Stops the scan with `id`. Returns false if there is no such scan or it is already over.
+pub fn cancel_scan(id: TransferSessionID) -> bool {
+    match get_sessions().lock().unwrap().get(id.into()) {
+        Some(config) if config.is_ongoing() => {
+            config.cancelled.store(true, Ordering::Relaxed);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// The state of the scan with `id`, handing out the groups confirmed since the last call.
+pub fn get_session_with_id(id: TransferSessionID) -> Option<DuplicateMsg> {
+    let config = get_sessions().lock().unwrap().get(id.into()).cloned();
+    config.map(|v| v.read())
+}
+
+/// Forgets finished scans, along with the groups they found that were not read.
+pub fn clean_dead_sessions() {
+    get_sessions().lock().unwrap().retain(|_, v| v.is_ongoing());
+}
+
+/// The files of `files` that still exist, newest first.
+fn newest_first(files: Vec<PitouFile>) -> Vec<(PathBuf, std::fs::Metadata)> {
+    let mut files = files
+        .into_iter()
+        .filter_map(|v| {
+            let metadata = std::fs::symlink_metadata(&v.path.path).ok()?;
+            metadata.is_file().then_some((v.path.path, metadata))
+        })
+        .collect::<Vec<_>>();
+    files.sort_by_key(|(_, v)| Reverse(v.modified().unwrap_or(SystemTime::UNIX_EPOCH)));
+    files
+}
+
+/// Moves every file of a duplicate group to the trash but the most recently modified. `None` if fewer than two of the
+/// files are still there.
+pub fn trash_all_but_newest(files: Vec<PitouFile>) -> Option<TransferSessionID> {
+    let files = newest_first(files);
+    if files.len() < 2 {
+        return None;
+    }
+    let rest = files
+        .into_iter()
+        .skip(1)
+        .map(|(path, metadata)| PitouFile::new(path, metadata))
+        .collect();
+    Some(deletion::trash(rest))
+}
+
+/// Replaces every file of a duplicate group with a hardlink to the most recently modified one, which frees their
+/// space but makes them share its permissions and times from then on. Files whose content no longer matches it or
+/// that sit on another filesystem are left alone and reported, like those that could not be replaced.
+pub fn replace_with_hardlinks(files: Vec<PitouFile>) -> Vec<TransferError> {
+    let mut files = newest_first(files).into_iter();
+    let Some((keep, kept)) = files.next() else {
+        return Vec::new();
+    };
+    let failure = |path: PathBuf, message: String| TransferError {
+        path: PitouFilePath::from_pathbuf(path),
+        message,
+    };
+    let content = match verify::checksum(&keep) {
+        Ok(content) => content,
+        Err(e) => {
+            return files
+                .map(|(path, _)| failure(path, e.to_string()))
+                .collect()
+        }
+    };
+    files
+        .filter_map(|(path, metadata)| {
+            link_over(&keep, &kept, content, &path, &metadata)
+                .err()
+                .map(|e| failure(path, e.to_string()))
+        })
+        .collect()
+}
+
+/// Puts a hardlink to `keep` in place of `path` by linking it beside `path` and renaming it over, so that `path`
+/// never goes missing.
+fn link_over(
+    keep: &Path,
+    kept: &std::fs::Metadata,
+    content: blake3::Hash,
+    path: &Path,
+    metadata: &std::fs::Metadata,
+) -> io::Result<()> {
+    if let (Some(a), Some(b)) = (usage::hardlink_id(kept), usage::hardlink_id(metadata)) {
+        if a == b {
+            return Ok(());
+        }
+    }
+    if same_device(kept, metadata) == Some(false) {
+        return Err(io::Error::other("the file is on another filesystem"));
+    }
+    if metadata.len() != kept.len() || verify::checksum(path)? != content {
+        return Err(io::Error::other("the file no longer has the same content"));
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{name}.pitou-link"));
+    std::fs::hard_link(keep, &temp)?;
+    std::fs::rename(&temp, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temp);
+    })
+}
+
+#[cfg(unix)]
+fn same_device(a: &std::fs::Metadata, b: &std::fs::Metadata) -> Option<bool> {
+    use std::os::unix::fs::MetadataExt;
+    Some(a.dev() == b.dev())
+}
+
+#[cfg(not(unix))]
+fn same_device(_: &std::fs::Metadata, _: &std::fs::Metadata) -> Option<bool> {
+    None
+}
+
+#[cfg(all(test, unix))]
+mod test_mod {
+    use std::os::unix::fs::MetadataExt;
+
+    use super::*;
+    use crate::backend::testing;
+
+    fn wait_for(id: TransferSessionID) -> (DuplicateMsg, Vec<DuplicateGroup>) {
+        let mut groups = Vec::new();
+        let read = || {
+            let mut msg = get_session_with_id(id).unwrap();
+            groups.append(&mut msg.groups);
+            msg
+        };
+        let msg = testing::wait_for(read, |msg| msg.state != ScanState::Scanning);
+        (msg, groups)
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let (a, b) = (root.join("a"), root.join("b").join("deep"));
+        std::fs::create_dir_all(&a).unwrap();
+        std::fs::create_dir_all(&b).unwrap();
+
+        // same heads, different tails
+        let mut big = vec![3; HEAD_LEN as usize + 10];
+        std::fs::write(a.join("big.bin"), &big).unwrap();
+        std::fs::write(b.join("big copy.bin"), &big).unwrap();
+        *big.last_mut().unwrap() = 4;
+        std::fs::write(b.join("big other.bin"), &big).unwrap();
+        std::fs::write(a.join("small.txt"), b"same").unwrap();
+        std::fs::write(b.join("small.txt"), b"same").unwrap();
+        std::fs::write(b.join("diff.txt"), b"diff").unwrap();
+        std::fs::hard_link(a.join("small.txt"), a.join("small link.txt")).unwrap();
+        std::fs::write(a.join("empty"), b"").unwrap();
+        std::fs::write(b.join("empty"), b"").unwrap();
+
+        let dirs = vec![
+            PitouFilePath::from_pathbuf(a.clone()),
+            PitouFilePath::from_pathbuf(root.join("b")),
+        ];
+        let (msg, groups) = wait_for(find_duplicates(dirs, PitouFileFilter::new(), 8));
+        assert_eq!(msg.state, ScanState::Completed);
+        assert_eq!((msg.groups_found, msg.errors), (2, 0));
+        assert_eq!(msg.wasted_bytes, HEAD_LEN + 10 + 4);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].size, HEAD_LEN + 10);
+        assert_eq!(groups[0].files.len(), 2);
+        assert_eq!(groups[1].files.len(), 2);
+
+        // a filter leaving files out finds nothing
+        let dirs = vec![PitouFilePath::from_pathbuf(root.to_path_buf())];
+        let (msg, _) = wait_for(find_duplicates(dirs, PitouFileFilter::only_dirs(), 8));
+        assert_eq!((msg.files, msg.groups_found), (0, 0));
+
+        let files = groups
+            .into_iter()
+            .next()
+            .unwrap()
+            .files
+            .into_iter()
+            .map(|v| {
+                PitouFile::new(
+                    v.path.path.clone(),
+                    std::fs::metadata(&v.path.path).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let paths = files
+            .iter()
+            .map(|v| v.path.path.clone())
+            .collect::<Vec<_>>();
+        assert!(replace_with_hardlinks(files).is_empty());
+        let inodes = paths
+            .iter()
+            .map(|v| std::fs::metadata(v).unwrap().ino())
+            .collect::<HashSet<_>>();
+        assert_eq!(inodes.len(), 1);
+
+        let changed = vec![b.join("small.txt"), b.join("diff.txt")]
+            .into_iter()
+            .map(|v| PitouFile::new(v.clone(), std::fs::metadata(&v).unwrap()))
+            .collect();
+        assert_eq!(replace_with_hardlinks(changed).len(), 1);
+    }
+}
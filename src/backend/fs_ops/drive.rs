@@ -1,18 +1,29 @@
-use crate::{PitouDrive, PitouDriveKind, PitouFilePath};
-use std::path::PathBuf;
+use crate::{
+    DriveEvent, DriveEventKind, PitouDrive, PitouDriveFileSystem, PitouDriveKind, PitouFilePath,
+};
+use std::path::{Path, PathBuf};
 use sysinfo::{Disk, DiskKind, Disks};
 
 impl PitouDrive {
     pub fn get_drives() -> Vec<Self> {
+        let table = MountTable::read();
         let mut drives = Disks::new_with_refreshed_list()
             .into_iter()
-            .map(|d| Self::to_drive(d))
+            .map(|d| Self::to_drive(d, &table))
             .collect::<Vec<_>>();
         drives.sort_unstable_by(|a, b| a.mount_point().as_bytes().cmp(&b.mount_point().as_bytes()));
         drives
     }
 
-    fn to_drive(disk: &Disk) -> Self {
+    /// Returns the drive whose mount point is the closest ancestor of `path`.
+    pub fn containing(path: &Path) -> Option<Self> {
+        Self::get_drives()
+            .into_iter()
+            .filter(|d| path.starts_with(&d.mount_point().path))
+            .max_by_key(|d| d.mount_point().len())
+    }
+
+    fn to_drive(disk: &Disk, table: &MountTable) -> Self {
         let mount_point = PitouFilePath::from_pathbuf(PathBuf::from(disk.mount_point()));
         let is_removable = disk.is_removable();
         let total_space = disk.total_space();
@@ -27,6 +38,7 @@ impl PitouDrive {
             .to_str()
             .map(|v| v.to_owned())
             .unwrap_or_default();
+        let filesystem = table.filesystem(disk);
 
         PitouDrive {
             mount_point,
@@ -35,6 +47,473 @@ impl PitouDrive {
             is_removable,
             kind,
             name,
+            filesystem,
+        }
+    }
+}
+
+const NETWORK_FILESYSTEMS: [&str; 14] = [
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "ncpfs",
+    "afs",
+    "9p",
+    "ceph",
+    "glusterfs",
+    "davfs",
+    "fuse.sshfs",
+    "fuse.rclone",
+    "fuse.s3fs",
+];
+
+/// Filesystems that hold no data of their own on a storage device. Squashfs is included for the images that snap and
+/// similar tools mount by the dozen.
+const VIRTUAL_FILESYSTEMS: [&str; 25] = [
+    "tmpfs",
+    "ramfs",
+    "overlay",
+    "squashfs",
+    "proc",
+    "sysfs",
+    "devtmpfs",
+    "devpts",
+    "devfs",
+    "cgroup",
+    "cgroup2",
+    "autofs",
+    "efivarfs",
+    "fusectl",
+    "debugfs",
+    "tracefs",
+    "securityfs",
+    "pstore",
+    "bpf",
+    "mqueue",
+    "hugetlbfs",
+    "configfs",
+    "binfmt_misc",
+    "nsfs",
+    "nullfs",
+];
+
+fn is_network(kind: &str) -> bool {
+    NETWORK_FILESYSTEMS.contains(&kind)
+}
+
+/// Fuse filesystems count as virtual unless they are known network ones. Block-device backed fuse mounts, such as
+/// ntfs-3g, show up as `fuseblk` instead.
+fn is_virtual(kind: &str) -> bool {
+    !is_network(kind)
+        && (VIRTUAL_FILESYSTEMS.contains(&kind) || kind == "fuse" || kind.starts_with("fuse."))
+}
+
+/// What the system tells about mounts beyond what `sysinfo` reports.
+struct MountTable {
+    #[cfg(target_os = "linux")]
+    mounts: Vec<MountInfo>,
+    /// devices with a label, by their canonical path
+    #[cfg(target_os = "linux")]
+    labels: Vec<(PathBuf, String)>,
+    #[cfg(target_os = "linux")]
+    uuids: Vec<(PathBuf, String)>,
+}
+
+impl MountTable {
+    #[cfg(target_os = "linux")]
+    fn read() -> Self {
+        Self {
+            mounts: MountInfo::read_all().unwrap_or_default(),
+            labels: device_names("/dev/disk/by-label"),
+            uuids: device_names("/dev/disk/by-uuid"),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn read() -> Self {
+        Self {}
+    }
+
+    fn filesystem(&self, disk: &Disk) -> PitouDriveFileSystem {
+        let kind = disk.file_system().to_string_lossy().into_owned();
+        let mut res = PitouDriveFileSystem {
+            is_network: is_network(&kind),
+            is_virtual: is_virtual(&kind),
+            kind,
+            ..Default::default()
+        };
+        // inode counts are narrower than u64 on some platforms
+        #[cfg(unix)]
+        #[allow(clippy::useless_conversion)]
+        if let Some(stat) = statvfs(disk.mount_point()) {
+            res.is_read_only = stat.f_flag & libc::ST_RDONLY != 0;
+            // filesystems without a fixed inode table report none
+            if stat.f_files != 0 {
+                res.total_inodes = Some(u64::from(stat.f_files));
+                res.free_inodes = Some(u64::from(stat.f_ffree));
+            }
+        }
+        #[cfg(target_os = "linux")]
+        self.fill_from_mountinfo(disk.mount_point(), &mut res);
+        res
+    }
+
+    #[cfg(target_os = "linux")]
+    fn fill_from_mountinfo(&self, mount_point: &Path, res: &mut PitouDriveFileSystem) {
+        // the last of stacked mounts is the one in effect
+        let Some(mount) = self
+            .mounts
+            .iter()
+            .rev()
+            .find(|v| v.mount_point == mount_point)
+        else {
+            return;
+        };
+        res.mount_options = mount.options.clone();
+        if !mount.source.starts_with('/') {
+            res.device = Some(mount.source.clone());
+            return;
         }
+        let device = std::fs::canonicalize(&mount.source).unwrap_or(PathBuf::from(&mount.source));
+        let find = |names: &[(PathBuf, String)]| {
+            names
+                .iter()
+                .find(|(v, _)| *v == device)
+                .map(|(_, name)| name.clone())
+        };
+        res.label = find(&self.labels);
+        res.uuid = find(&self.uuids);
+        res.device = Some(mount.source.clone());
+    }
+}
+
+#[cfg(unix)]
+fn statvfs(path: &Path) -> Option<libc::statvfs> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::uninit();
+    let res = unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) };
+    (res == 0).then(|| unsafe { stat.assume_init() })
+}
+
+/// One line of `/proc/self/mountinfo`.
+#[cfg(target_os = "linux")]
+struct MountInfo {
+    mount_point: PathBuf,
+    options: Vec<String>,
+    source: String,
+}
+
+#[cfg(target_os = "linux")]
+impl MountInfo {
+    fn read_all() -> Option<Vec<Self>> {
+        let content = std::fs::read_to_string("/proc/self/mountinfo").ok()?;
+        Some(content.lines().filter_map(Self::parse).collect())
+    }
+
+    /// The fifth field is the mount point and the sixth its options. Optional fields follow, up to a lone `-`, and then
+    /// come the filesystem type and its source. Spaces and other separators are escaped as octal.
+    fn parse(line: &str) -> Option<Self> {
+        let fields = line.split(' ').collect::<Vec<_>>();
+        let separator = fields.iter().position(|v| *v == "-")?;
+        Some(Self {
+            mount_point: PathBuf::from(unescape_octal(fields.get(4)?)),
+            options: fields.get(5)?.split(',').map(str::to_owned).collect(),
+            source: unescape_octal(fields.get(separator + 2)?)
+                .to_string_lossy()
+                .into_owned(),
+        })
+    }
+}
+
+/// The links in `dir`, such as `/dev/disk/by-label`, as canonical device paths and the names of the links. udev
+/// escapes unsafe characters of labels as `\xHH`.
+#[cfg(target_os = "linux")]
+fn device_names(dir: &str) -> Vec<(PathBuf, String)> {
+    let Ok(rd) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    rd.flatten()
+        .filter_map(|entry| {
+            let device = std::fs::canonicalize(entry.path()).ok()?;
+            let name = entry.file_name();
+            let name = name.to_str()?;
+            let mut bytes = Vec::with_capacity(name.len());
+            let mut idx = 0;
+            while idx < name.len() {
+                let code = name
+                    .get(idx..idx + 4)
+                    .and_then(|v| v.strip_prefix("\\x"))
+                    .and_then(|v| u8::from_str_radix(v, 16).ok());
+                match code {
+                    Some(code) => {
+                        bytes.push(code);
+                        idx += 4;
+                    }
+                    None => {
+                        bytes.push(name.as_bytes()[idx]);
+                        idx += 1;
+                    }
+                }
+            }
+            Some((device, String::from_utf8_lossy(&bytes).into_owned()))
+        })
+        .collect()
+}
+
+/// Every mount point of the system, including the ones `get_drives` leaves out such as tmpfs and bind mounts.
+#[cfg(target_os = "linux")]
+pub(crate) fn mount_points() -> Vec<PathBuf> {
+    match MountInfo::read_all() {
+        Some(mounts) => mounts.into_iter().map(|v| v.mount_point).collect(),
+        None => PitouDrive::get_drives()
+            .into_iter()
+            .map(|d| d.mount_point.path)
+            .collect(),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn mount_points() -> Vec<PathBuf> {
+    PitouDrive::get_drives()
+        .into_iter()
+        .map(|d| d.mount_point.path)
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn unescape_octal(field: &str) -> std::ffi::OsString {
+    use std::os::unix::ffi::OsStringExt;
+    let bytes = field.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let code = bytes.get(idx + 1..idx + 4).and_then(|v| {
+            let v = std::str::from_utf8(v).ok()?;
+            u8::from_str_radix(v, 8).ok()
+        });
+        match code {
+            Some(code) if bytes[idx] == b'\\' => {
+                res.push(code);
+                idx += 4;
+            }
+            _ => {
+                res.push(bytes[idx]);
+                idx += 1;
+            }
+        }
+    }
+    std::ffi::OsString::from_vec(res)
+}
+
+/// The events that turn the drive list `old` into `new`.
+fn diff(old: &[PitouDrive], new: &[PitouDrive]) -> Vec<DriveEvent> {
+    let removed = old.iter().filter(|d| !new.contains(d)).map(|d| DriveEvent {
+        kind: DriveEventKind::Removed,
+        drive: d.clone(),
+    });
+    let added_or_changed = new.iter().filter_map(|d| {
+        let kind = match old.iter().find(|v| *v == d) {
+            None => DriveEventKind::Added,
+            Some(v) if !same_state(v, d) => DriveEventKind::Changed,
+            Some(_) => return None,
+        };
+        Some(DriveEvent {
+            kind,
+            drive: d.clone(),
+        })
+    });
+    removed.chain(added_or_changed).collect()
+}
+
+/// Free space is left out, as it changes with nearly every write and would flood the events.
+fn same_state(a: &PitouDrive, b: &PitouDrive) -> bool {
+    a.name == b.name
+        && a.total_space == b.total_space
+        && a.is_removable == b.is_removable
+        && a.kind == b.kind
+        && a.filesystem == b.filesystem
+}
+
+/// Watches the drives from a thread of its own, started by the first call to [`start`].
+pub(crate) mod monitor {
+    use std::{
+        collections::VecDeque,
+        sync::{Mutex, Once},
+        time::Duration,
+    };
+
+    use super::diff;
+    use crate::{DriveEvent, PitouDrive};
+
+    /// How often the drives are looked at when nothing is mounted or unmounted, which catches changes that the mount
+    /// table does not signal.
+    const POLL_INTERVAL: Duration = Duration::from_secs(2);
+    /// Events kept for the next call to [`events`]. Older ones are dropped when nobody takes them.
+    const MAX_EVENTS: usize = 256;
+
+    static EVENTS: Mutex<VecDeque<DriveEvent>> = Mutex::new(VecDeque::new());
+    static STARTED: Once = Once::new();
+
+    /// Starts the monitor if it is not running yet. Changes made before that are not reported.
+    pub(crate) fn start() {
+        STARTED.call_once(|| {
+            let drives = PitouDrive::get_drives();
+            std::thread::spawn(move || watch(drives));
+        })
+    }
+
+    /// Takes the events that happened since the last call, oldest first, starting the monitor on the first call. At
+    /// most [`MAX_EVENTS`] are kept in between.
+    pub(crate) fn events() -> Vec<DriveEvent> {
+        start();
+        std::mem::take(&mut *EVENTS.lock().unwrap()).into()
+    }
+
+    fn record(queue: &mut VecDeque<DriveEvent>, events: Vec<DriveEvent>) {
+        queue.extend(events);
+        let excess = queue.len().saturating_sub(MAX_EVENTS);
+        queue.drain(..excess);
+    }
+
+    fn watch(mut drives: Vec<PitouDrive>) {
+        let mut mounts = Mounts::open();
+        loop {
+            mounts.wait();
+            let current = PitouDrive::get_drives();
+            let events = diff(&drives, &current);
+            if !events.is_empty() {
+                record(&mut EVENTS.lock().unwrap(), events);
+            }
+            drives = current;
+        }
+    }
+
+    /// The mount table of the process. On Linux, polling it wakes up as soon as something is mounted or unmounted.
+    struct Mounts {
+        #[cfg(target_os = "linux")]
+        file: Option<std::fs::File>,
+    }
+
+    impl Mounts {
+        #[cfg(target_os = "linux")]
+        fn open() -> Self {
+            Self {
+                file: std::fs::File::open("/proc/self/mountinfo").ok(),
+            }
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        fn open() -> Self {
+            Self {}
+        }
+
+        /// Returns when the mount table changed or when the poll interval passed.
+        #[cfg(target_os = "linux")]
+        fn wait(&mut self) {
+            use std::os::fd::AsRawFd;
+            let Some(file) = &self.file else {
+                return std::thread::sleep(POLL_INTERVAL);
+            };
+            let mut fd = libc::pollfd {
+                fd: file.as_raw_fd(),
+                events: libc::POLLPRI,
+                revents: 0,
+            };
+            let res = unsafe { libc::poll(&mut fd, 1, POLL_INTERVAL.as_millis() as i32) };
+            if res < 0 {
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        fn wait(&mut self) {
+            std::thread::sleep(POLL_INTERVAL)
+        }
+    }
+
+    #[cfg(test)]
+    mod test_mod {
+        use super::*;
+        use crate::DriveEventKind;
+
+        #[test]
+        fn test_event_cap() {
+            let event = |free_space| DriveEvent {
+                kind: DriveEventKind::Changed,
+                drive: super::super::test_mod::drive("/", free_space),
+            };
+            let mut queue = VecDeque::new();
+            record(&mut queue, (0..200).map(event).collect());
+            record(&mut queue, (200..300).map(event).collect());
+            assert_eq!(queue.len(), MAX_EVENTS);
+            assert_eq!(
+                queue.front().unwrap().drive.free_space,
+                300 - MAX_EVENTS as u64
+            );
+            assert_eq!(queue.back().unwrap().drive.free_space, 299);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_mod {
+    use super::*;
+
+    pub(super) fn drive(mount_point: &str, free_space: u64) -> PitouDrive {
+        PitouDrive {
+            name: String::from("disk"),
+            mount_point: PitouFilePath::from_pathbuf(PathBuf::from(mount_point)),
+            total_space: 100,
+            free_space,
+            is_removable: false,
+            kind: PitouDriveKind::SSD,
+            filesystem: PitouDriveFileSystem::default(),
+        }
+    }
+
+    #[test]
+    fn test_drive_diff() {
+        let renamed = PitouDrive {
+            name: String::from("system"),
+            ..drive("/", 50)
+        };
+        let old = [drive("/", 50), drive("/media/usb", 10)];
+        let new = [renamed, drive("/media/sd", 90)];
+        let events = diff(&old, &new)
+            .into_iter()
+            .map(|v| (v.kind, v.drive.mount_point.path))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                (DriveEventKind::Removed, PathBuf::from("/media/usb")),
+                (DriveEventKind::Changed, PathBuf::from("/")),
+                (DriveEventKind::Added, PathBuf::from("/media/sd")),
+            ]
+        );
+        assert!(diff(&new, &new).is_empty());
+        // free space alone is no change
+        assert!(diff(&[drive("/", 50)], &[drive("/", 40)]).is_empty());
+    }
+
+    #[test]
+    fn test_filesystem_kinds() {
+        assert!(is_virtual("overlay") && is_virtual("fuse.gvfsd-fuse") && is_virtual("squashfs"));
+        assert!(is_network("nfs4") && is_network("fuse.sshfs") && !is_virtual("fuse.sshfs"));
+        assert!(!is_virtual("ext4") && !is_virtual("fuseblk") && !is_network("btrfs"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_mountinfo() {
+        let line = "36 35 98:0 / /media/my\\040usb ro,nosuid shared:1 master:2 - vfat /dev/sdb1 rw,fmask=0022";
+        let mount = MountInfo::parse(line).unwrap();
+        assert!(mount.mount_point == Path::new("/media/my usb"));
+        assert_eq!(mount.options, ["ro", "nosuid"]);
+        assert_eq!(mount.source, "/dev/sdb1");
+        assert!(MountInfo::parse("36 35 98:0 / /mnt rw").is_none());
     }
 }
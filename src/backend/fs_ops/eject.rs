@@ -0,0 +1,168 @@
+//! Unmounting and ejecting removable drives.
+//!
+//! Drives are unmounted through the tools of the system: `udisksctl` (falling back to `umount`) on Linux and `diskutil`
+//! on macOS, which let a regular user unmount what they could mount.
+
+use std::path::Path;
+
+use crate::{
+    backend::{deletion, transfer},
+    msg::{DriveBlocker, EjectOutcome},
+    PitouDrive,
+};
+
+/// Ongoing sessions and processes using anything under `mount_point`. Processes of other users cannot be inspected and
+/// are not reported.
+pub(crate) fn blockers(mount_point: &Path) -> Vec<DriveBlocker> {
+    let transfers = transfer::sessions_within(mount_point)
+        .into_iter()
+        .map(DriveBlocker::Transfer);
+    let deletions = deletion::sessions_within(mount_point)
+        .into_iter()
+        .map(DriveBlocker::Deletion);
+    transfers
+        .chain(deletions)
+        .chain(processes_within(mount_point))
+        .collect()
+}
+
+/// Unmounts `drive` once nothing uses it, and powers its device off if `eject` is set.
+pub(crate) fn unmount(drive: &PitouDrive, eject: bool) -> EjectOutcome {
+    if !drive.is_removable {
+        return EjectOutcome::Failed(String::from("only removable drives can be unmounted"));
+    }
+    let mount_point = &drive.mount_point.path;
+    let blockers = blockers(mount_point);
+    if !blockers.is_empty() {
+        return EjectOutcome::Blocked(blockers);
+    }
+    if let Err(e) = unmount_now(drive) {
+        return EjectOutcome::Failed(e);
+    }
+    match eject && power_off(drive).is_ok() {
+        true => EjectOutcome::Ejected,
+        false => EjectOutcome::Unmounted,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn unmount_now(drive: &PitouDrive) -> Result<(), String> {
+    let mount_point = drive.mount_point.path.as_os_str();
+    match &drive.filesystem.device {
+        Some(device) if device.starts_with("/dev/") => {
+            let args = ["unmount", "--no-user-interaction", "-b", device];
+            run("udisksctl", &args).or_else(|_| run("umount", &[mount_point]))
+        }
+        _ => run("umount", &[mount_point]),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn power_off(drive: &PitouDrive) -> Result<(), String> {
+    match &drive.filesystem.device {
+        Some(device) if device.starts_with("/dev/") => run(
+            "udisksctl",
+            &["power-off", "--no-user-interaction", "-b", device],
+        ),
+        _ => Err(String::from("the drive has no device to power off")),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn unmount_now(drive: &PitouDrive) -> Result<(), String> {
+    let mount_point = drive.mount_point.path.as_os_str();
+    run("diskutil", &[std::ffi::OsStr::new("unmount"), mount_point])
+}
+
+#[cfg(target_os = "macos")]
+fn power_off(drive: &PitouDrive) -> Result<(), String> {
+    match &drive.filesystem.device {
+        Some(device) => run("diskutil", &["eject", device]),
+        None => Err(String::from("the drive has no device to eject")),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn unmount_now(_: &PitouDrive) -> Result<(), String> {
+    Err(String::from("unmounting is not supported on this platform"))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn power_off(_: &PitouDrive) -> Result<(), String> {
+    Err(String::from("ejecting is not supported on this platform"))
+}
+
+/// Runs `program`, turning a failure into the message it printed.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn run<S: AsRef<std::ffi::OsStr>>(program: &str, args: &[S]) -> Result<(), String> {
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| format!("cannot run {program}: {e}"))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let message = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+    match message.is_empty() {
+        true => Err(format!("{program} failed with {}", output.status)),
+        false => Err(message),
+    }
+}
+
+/// Processes with open files or their working folder under `mount_point`, read from `/proc`.
+#[cfg(target_os = "linux")]
+fn processes_within(mount_point: &Path) -> Vec<DriveBlocker> {
+    let Ok(rd) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let within = |link: &Path| std::fs::read_link(link).is_ok_and(|v| v.starts_with(mount_point));
+    rd.flatten()
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+            let dir = entry.path();
+            let open_files = std::fs::read_dir(dir.join("fd"))
+                .ok()?
+                .flatten()
+                .filter(|fd| within(&fd.path()))
+                .count() as u32;
+            let open_files = open_files + within(&dir.join("cwd")) as u32;
+            if open_files == 0 {
+                return None;
+            }
+            let name = std::fs::read_to_string(dir.join("comm")).unwrap_or_default();
+            Some(DriveBlocker::Process {
+                pid,
+                name: name.trim_end().to_owned(),
+                open_files,
+            })
+        })
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn processes_within(_: &Path) -> Vec<DriveBlocker> {
+    Vec::new()
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test_mod {
+    use super::*;
+
+    #[test]
+    fn test_open_file_blocks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let file = std::fs::File::create(root.join("open.txt")).unwrap();
+
+        let own = std::process::id();
+        let blocked_by_self = |v: &DriveBlocker| match v {
+            DriveBlocker::Process {
+                pid, open_files, ..
+            } => *pid == own && *open_files >= 1,
+            _ => false,
+        };
+        assert!(blockers(root).iter().any(blocked_by_self));
+        std::mem::drop(file);
+        assert!(!blockers(root).iter().any(blocked_by_self));
+    }
+}
@@ -1,20 +1,23 @@
 use std::{
     fs::{FileType, Metadata},
-    os::windows::fs::MetadataExt,
     path::PathBuf,
-    str::FromStr,
+    sync::{Mutex, OnceLock},
     time::SystemTime,
 };
 
 use crate::{
-    GeneralFolder, PitouDateTime, PitouDrive, PitouFile, PitouFileFilter, PitouFileKind,
-    PitouFileMetadata, PitouFilePath, PitouFileSize, PitouFileSort, PitouTrashItem,
-    PitouTrashItemMetadata,
+    msg::{
+        DriveBlocker, EjectOutcome, RestoreOptions, RestoreOutcome, RestoreResult,
+        TransferSessionID,
+    },
+    DriveEvent, GeneralFolder, PitouDateTime, PitouDrive, PitouDriveFilter, PitouFile,
+    PitouFileFilter, PitouFileKind, PitouFileMetadata, PitouFilePath, PitouFileSort,
+    PitouTrashItem, TrashPreview, TrashPurgeSummary, TrashQuery, TrashRetention, TrashVolumeStats,
 };
 use chrono::DateTime;
-use trash::TrashItem;
 
 pub mod drive;
+mod eject;
 
 pub mod clipboard {
     use std::sync::{Arc, OnceLock};
@@ -49,6 +52,14 @@ pub mod clipboard {
             .push(ClipboardItem::Cut(Arc::new(files)))
     }
 
+    /// Returns the item that the next paste would use, without consuming it.
+    pub(crate) async fn peek() -> Option<ClipboardItem> {
+        get_clipboard().lock().await.last().map(|v| match v {
+            ClipboardItem::Copied(u) => ClipboardItem::Copied(u.clone()),
+            ClipboardItem::Cut(u) => ClipboardItem::Cut(u.clone()),
+        })
+    }
+
     pub async fn remove_from_clipboard(idx: usize) {
         get_clipboard().lock().await.remove(idx);
     }
@@ -77,16 +88,42 @@ pub mod clipboard {
     }
 }
 
+/// The drives on local storage devices and network shares. Pseudo filesystems are left out.
 pub fn drives() -> Vec<PitouDrive> {
+    drives_with(PitouDriveFilter::default())
+}
+
+pub fn drives_with(filter: PitouDriveFilter) -> Vec<PitouDrive> {
     let mut drives = PitouDrive::get_drives();
+    drives.retain(|d| filter.includes(d));
     drives.sort_unstable_by(|a, b| a.mount_point.name().cmp(b.mount_point.name()));
     drives
 }
 
-pub fn delete(items: Vec<PitouFile>) {
-    for item in items {
-        tokio::spawn(async move { trash::delete(&item.path.path) });
-    }
+/// What keeps `drive` from being unmounted right now: transfer and delete sessions touching it and processes using it.
+pub fn drive_blockers(drive: &PitouDrive) -> Vec<DriveBlocker> {
+    eject::blockers(&drive.mount_point.path)
+}
+
+/// Unmounts a removable drive, unless something still uses it.
+pub fn unmount_drive(drive: &PitouDrive) -> EjectOutcome {
+    eject::unmount(drive, false)
+}
+
+/// Unmounts a removable drive and powers it off so that it can be unplugged, unless something still uses it.
+pub fn eject_drive(drive: &PitouDrive) -> EjectOutcome {
+    eject::unmount(drive, true)
+}
+
+/// Drives mounted, unmounted or changed since the last call, oldest first. Watching starts with the first call, so a
+/// caller that wants events makes one right after listing the [`drives`].
+pub fn drive_events() -> Vec<DriveEvent> {
+    drive::monitor::events()
+}
+
+/// Moves `items` to the trash. The returned id follows the session in [`crate::backend::deletion`].
+pub fn delete(items: Vec<PitouFile>) -> TransferSessionID {
+    super::deletion::trash(items)
 }
 
 pub async fn copy(items: Vec<PitouFile>) {
@@ -249,80 +286,97 @@ pub fn general_folders() -> Vec<GeneralFolder> {
 }
 
 pub fn trash_items() -> Option<Vec<PitouTrashItem>> {
-    trash::os_limited::list()
-        .map(|v| v.into_iter().filter_map(|u| u.try_into().ok()).collect())
-        .ok()
+    super::trash_ops::list().ok()
+}
+
+/// The items of the trash that match `query`. `None` if the trash cannot be read or the query pattern is invalid.
+pub fn query_trash(query: &TrashQuery) -> Option<Vec<PitouTrashItem>> {
+    super::trash_ops::query(super::trash_ops::list().ok()?, query)
+}
+
+/// Restores `items` to where they were deleted from, renaming any that would land on an existing item.
+pub fn restore_trash(items: impl Iterator<Item = PitouTrashItem>) -> Vec<RestoreResult> {
+    restore_trash_with(items, &RestoreOptions::default())
+}
+
+pub fn restore_trash_with(
+    items: impl Iterator<Item = PitouTrashItem>,
+    options: &RestoreOptions,
+) -> Vec<RestoreResult> {
+    let results = items
+        .map(|item| {
+            let (outcome, restored_to) = match super::trash_ops::restore(&item, options) {
+                Ok(Some(path)) => (
+                    RestoreOutcome::Restored,
+                    Some(PitouFilePath::from_pathbuf(path)),
+                ),
+                Ok(None) => (RestoreOutcome::Skipped, None),
+                Err(e) => (RestoreOutcome::Failed(e.to_string()), None),
+            };
+            RestoreResult {
+                id: item.metadata.id,
+                outcome,
+                restored_to,
+            }
+        })
+        .collect();
+    // a restore that replaces an item trashes it
+    apply_trash_retention();
+    results
+}
+
+pub fn purge_trash(items: impl Iterator<Item = PitouTrashItem>) {
+    for item in items {
+        super::trash_ops::purge(&item).ok();
+    }
 }
 
-pub fn restore_trash(items: impl Iterator<Item = PitouTrashItem>) {
-    trash::os_limited::restore_all(items.map(|v| <PitouTrashItem as Into<TrashItem>>::into(v)))
-        .ok();
+/// Shows what `item` holds without restoring it.
+pub fn preview_trash_item(item: &PitouTrashItem) -> std::io::Result<TrashPreview> {
+    super::trash_ops::preview(&super::trash_ops::location(item)?)
 }
 
-pub fn purge_trash(items: impl Iterator<Item = PitouTrashItem>) {
-    trash::os_limited::purge_all(items.map(|v| <PitouTrashItem as Into<TrashItem>>::into(v))).ok();
-}
-
-impl From<PitouTrashItem> for TrashItem {
-    fn from(
-        PitouTrashItem {
-            mut original_path,
-            metadata:
-                PitouTrashItemMetadata {
-                    id,
-                    deleted,
-                    size: _,
-                    is_dir: _,
-                },
-        }: PitouTrashItem,
-    ) -> Self {
-        let time_deleted = deleted.datetime.and_utc().timestamp();
-        let name = original_path.name().to_owned();
-        original_path.path.pop();
-        let original_parent = original_path.path;
+/// Size and item count of the trash on every volume that has trashed items.
+pub fn trash_stats() -> Option<Vec<TrashVolumeStats>> {
+    let items = super::trash_ops::list().ok()?;
+    Some(super::trash_ops::stats(&items, &drive::mount_points()))
+}
 
-        Self {
-            id: std::ffi::OsString::from_str(&id).unwrap(),
-            name,
-            time_deleted,
-            original_parent,
-        }
-    }
+fn get_trash_retention() -> &'static Mutex<TrashRetention> {
+    static TRASH_RETENTION: OnceLock<Mutex<TrashRetention>> = OnceLock::new();
+    TRASH_RETENTION.get_or_init(|| Mutex::new(TrashRetention::default()))
 }
 
-impl TryFrom<TrashItem> for PitouTrashItem {
-    type Error = trash::Error;
-    fn try_from(item: TrashItem) -> Result<Self, Self::Error> {
-        let (size, is_dir) = match trash::os_limited::metadata(&item)?.size {
-            trash::TrashItemSize::Bytes(val) => (val, false),
-            trash::TrashItemSize::Entries(val) => (val as u64, true),
-        };
+/// Sets the limits that the trash is kept within from now on, usually the ones of the app settings, and enforces
+/// them right away. They are enforced again after every trash session and every restore, without limits until set.
+pub fn set_trash_retention(retention: TrashRetention) -> Option<TrashPurgeSummary> {
+    *get_trash_retention().lock().unwrap() = retention;
+    enforce_trash_retention(&retention)
+}
 
-        let TrashItem {
-            id,
-            name,
-            mut original_parent,
-            time_deleted,
-        } = item;
-
-        original_parent.push(name);
-
-        let metadata = PitouTrashItemMetadata {
-            id: id.into_string().unwrap(),
-            deleted: PitouDateTime {
-                datetime: DateTime::from_timestamp_millis(1000 * time_deleted)
-                    .unwrap()
-                    .naive_utc(),
-            },
-            is_dir,
-            size: PitouFileSize::new(size),
-        };
+/// Enforces the limits last given to [`set_trash_retention`].
+pub(crate) fn apply_trash_retention() {
+    let retention = *get_trash_retention().lock().unwrap();
+    if retention != TrashRetention::default() {
+        enforce_trash_retention(&retention);
+    }
+}
 
-        Ok(PitouTrashItem {
-            original_path: PitouFilePath::from_pathbuf(original_parent),
-            metadata,
-        })
+/// Purges the items that `retention` no longer allows in the trash.
+pub fn enforce_trash_retention(retention: &TrashRetention) -> Option<TrashPurgeSummary> {
+    let items = super::trash_ops::list().ok()?;
+    let now = chrono::Utc::now().naive_utc();
+    let mut summary = TrashPurgeSummary::default();
+    for item in super::trash_ops::expired(&items, retention, now) {
+        match super::trash_ops::purge(item) {
+            Ok(()) => {
+                summary.purged += 1;
+                summary.bytes_freed += item.metadata.size.bytes;
+            }
+            Err(_) => summary.failed += 1,
+        }
     }
+    Some(summary)
 }
 
 impl From<FileType> for PitouFileKind {
@@ -367,7 +421,18 @@ impl From<Metadata> for PitouFileMetadata {
             created: value.created().unwrap().into(),
             size: value.len().into(),
             kind: value.file_type().into(),
-            attribute: value.file_attributes(),
+            attribute: file_attributes(&value),
         }
     }
 }
+
+#[cfg(windows)]
+fn file_attributes(metadata: &Metadata) -> u32 {
+    use std::os::windows::fs::MetadataExt;
+    metadata.file_attributes()
+}
+
+#[cfg(not(windows))]
+fn file_attributes(_metadata: &Metadata) -> u32 {
+    0
+}
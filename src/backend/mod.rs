@@ -1,7 +1,15 @@
 mod fs_ops;
 mod ser_de;
+mod trash_ops;
+#[cfg(test)]
+mod testing;
 
+pub mod compare;
+pub mod deletion;
+pub mod duplicates;
+pub mod reports;
 pub mod search;
 pub mod transfer;
+pub mod usage;
 
 pub use fs_ops::*;
@@ -0,0 +1,225 @@
+//! Reports over the files of a folder tree, for the periodic cleanup of shared drives: the largest files, those left
+//! untouched for a while and empty items.
+//!
+//! The items of a report are acted on in bulk through the usual operations, [`crate::backend::delete`] to trash them
+//! and [`crate::backend::transfer::copy_items`] to copy them elsewhere, or through [`remove_empty`].
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::Metadata,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    backend::search,
+    msg::{ReportKind, TransferError},
+    PitouFile, PitouFileFilter, PitouFilePath,
+};
+
+/// Everything but links, hidden items included.
+const REPORTED: PitouFileFilter = PitouFileFilter {
+    files: true,
+    links: false,
+    dirs: true,
+    sys_items: true,
+};
+
+/// The items under `dir`, down to `depth` levels, that a report of `kind` lists.
+pub async fn report(dir: PitouFilePath, kind: ReportKind, depth: u8) -> Vec<PitouFile> {
+    tokio::task::spawn_blocking(move || collect(&dir.path, kind, depth, SystemTime::now()))
+        .await
+        .unwrap_or_default()
+}
+
+fn collect(dir: &Path, kind: ReportKind, depth: u8, now: SystemTime) -> Vec<PitouFile> {
+    let days = |days: u32| now - Duration::from_secs(days as u64 * 24 * 60 * 60);
+    let found = match kind {
+        ReportKind::Largest(count) => largest(dir, depth, count as usize),
+        ReportKind::NotModified(days_ago) => older(dir, depth, days(days_ago), Metadata::modified),
+        ReportKind::NotAccessed(days_ago) => older(dir, depth, days(days_ago), Metadata::accessed),
+        ReportKind::EmptyFiles => {
+            let mut found = files(dir, depth, |_, v| v.len() == 0);
+            found.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+            found
+        }
+        ReportKind::EmptyDirs => {
+            let mut found = Vec::new();
+            empty_dirs(dir, depth, &mut found);
+            found.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+            found
+        }
+    };
+    found
+        .into_iter()
+        .map(|(path, metadata)| PitouFile::new(path, metadata))
+        .collect()
+}
+
+/// The regular files under `dir` for which `keep` holds.
+fn files(
+    dir: &Path,
+    depth: u8,
+    mut keep: impl FnMut(&Path, &Metadata) -> bool,
+) -> Vec<(PathBuf, Metadata)> {
+    let mut found = Vec::new();
+    search::walk(dir, depth, REPORTED, &mut |file| {
+        let path = file.path.path;
+        if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+            if metadata.is_file() && keep(&path, &metadata) {
+                found.push((path, metadata));
+            }
+        }
+        true
+    });
+    found
+}
+
+fn largest(dir: &Path, depth: u8, count: usize) -> Vec<(PathBuf, Metadata)> {
+    if count == 0 {
+        return Vec::new();
+    }
+    // the smallest of the largest so far on top, so that only `count` files are held at any time
+    let mut heap = BinaryHeap::with_capacity(count + 1);
+    files(dir, depth, |path, metadata| {
+        heap.push(Reverse((metadata.len(), path.to_path_buf())));
+        if heap.len() > count {
+            heap.pop();
+        }
+        false
+    });
+    heap.into_sorted_vec()
+        .into_iter()
+        .filter_map(|Reverse((_, path))| {
+            let metadata = std::fs::symlink_metadata(&path).ok()?;
+            Some((path, metadata))
+        })
+        .collect()
+}
+
+/// The files whose `time` is before `cutoff`, oldest first.
+fn older(
+    dir: &Path,
+    depth: u8,
+    cutoff: SystemTime,
+    time: fn(&Metadata) -> std::io::Result<SystemTime>,
+) -> Vec<(PathBuf, Metadata)> {
+    let mut found = files(dir, depth, |_, v| time(v).is_ok_and(|v| v < cutoff));
+    found.sort_by_key(|(_, v)| time(v).ok());
+    found
+}
+
+/// Adds the outermost empty folders under `dir` to `found` and tells whether `dir` itself is empty, in which case its
+/// parent reports it rather than what it holds. Folders at the depth limit only count as empty if they hold nothing at all.
+fn empty_dirs(dir: &Path, depth: u8, found: &mut Vec<(PathBuf, Metadata)>) -> bool {
+    let Ok(read_dir) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    let mut empty = Vec::new();
+    let mut is_empty = true;
+    for entry in read_dir {
+        let Ok(entry) = entry else {
+            is_empty = false;
+            continue;
+        };
+        let path = entry.path();
+        match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() && depth > 0 => {
+                let start = found.len();
+                if empty_dirs(&path, depth - 1, found) {
+                    // reported through this folder instead
+                    found.truncate(start);
+                    empty.push((path, metadata));
+                } else {
+                    is_empty = false;
+                }
+            }
+            _ => is_empty = false,
+        }
+    }
+    found.extend(empty);
+    is_empty
+}
+
+/// Deletes the empty files and folders of `items` for good, which skips the trash as there is nothing to restore.
+/// Items that are no longer empty are left alone and reported, like those that could not be deleted.
+pub fn remove_empty(items: Vec<PitouFile>) -> Vec<TransferError> {
+    items
+        .into_iter()
+        .filter_map(|item| {
+            let path = item.path.path;
+            let res = match std::fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.is_dir() => remove_empty_dir(&path),
+                Ok(metadata) if metadata.is_file() && metadata.len() == 0 => {
+                    std::fs::remove_file(&path)
+                }
+                Ok(_) => Err(std::io::Error::other("the item is no longer empty")),
+                Err(e) => Err(e),
+            };
+            res.err().map(|e| TransferError {
+                path: PitouFilePath::from_pathbuf(path),
+                message: e.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Removes `dir` and the folders inside it, innermost first. Removing a folder fails if it holds anything else, so
+/// nothing that showed up since the report can be lost.
+fn remove_empty_dir(dir: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            remove_empty_dir(&entry.path())?;
+        }
+    }
+    std::fs::remove_dir(dir)
+}
+
+#[cfg(test)]
+mod test_mod {
+    use super::*;
+
+    #[test]
+    fn test_reports() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        std::fs::create_dir_all(root.join("old")).unwrap();
+        std::fs::create_dir_all(root.join("hollow").join("inner").join("deeper")).unwrap();
+        std::fs::create_dir_all(root.join("kept").join("vacant")).unwrap();
+        std::fs::write(root.join("kept").join("zero.txt"), b"").unwrap();
+        std::fs::write(root.join("big.bin"), vec![0; 300]).unwrap();
+        std::fs::write(root.join("old").join("mid.bin"), vec![0; 200]).unwrap();
+        std::fs::write(root.join("small.bin"), vec![0; 100]).unwrap();
+        let now = SystemTime::now();
+        let long_ago = filetime::FileTime::from_system_time(now - Duration::from_secs(90 * 86400));
+        filetime::set_file_times(root.join("old").join("mid.bin"), long_ago, long_ago).unwrap();
+
+        let names = |kind| {
+            collect(root, kind, 8, now)
+                .into_iter()
+                .map(|v| v.path.path.strip_prefix(root).unwrap().to_path_buf())
+                .collect::<Vec<_>>()
+        };
+        let paths = |v: &[&str]| v.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert_eq!(
+            names(ReportKind::Largest(2)),
+            paths(&["big.bin", "old/mid.bin"])
+        );
+        assert_eq!(names(ReportKind::NotModified(30)), paths(&["old/mid.bin"]));
+        assert_eq!(names(ReportKind::NotAccessed(30)), paths(&["old/mid.bin"]));
+        assert_eq!(names(ReportKind::EmptyFiles), paths(&["kept/zero.txt"]));
+        assert_eq!(
+            names(ReportKind::EmptyDirs),
+            paths(&["hollow", "kept/vacant"])
+        );
+
+        let items = collect(root, ReportKind::EmptyDirs, 8, now);
+        std::fs::write(root.join("kept").join("vacant").join("new.txt"), b"").unwrap();
+        let failures = remove_empty(items);
+        assert_eq!(failures.len(), 1);
+        assert!(!root.join("hollow").exists());
+        assert!(root.join("kept").join("vacant").join("new.txt").exists());
+    }
+}
@@ -1,4 +1,7 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use crate::{search::SimplifiedSearchOptions, PitouFile, PitouFileFilter};
 
@@ -230,11 +233,7 @@ impl From<SearchOptions> for (SearchVariables, PathBuf) {
 
 impl SearchVariables {
     fn include(&self, file: &PitouFile) -> bool {
-        ((file.is_file() && self.filter.files)
-            || (file.is_dir() && self.filter.dirs)
-            || (file.is_link() && self.filter.links)
-            || (file.is_sys_item() && self.filter.sys_items))
-            && self.search_type.matches(file.name(), self.case_sensitive)
+        self.filter.includes(file) && self.search_type.matches(file.name(), self.case_sensitive)
     }
 }
 
@@ -294,6 +293,36 @@ async fn recursive_search(directory: PathBuf, mut variables: SearchVariables) {
     }
 }
 
+/// Walks `directory` on the calling thread, going down `depth` levels like a search does and handing `visit` every
+/// item that `filter` lets through. Links are not followed. Returns false if `visit` stopped the walk by returning
+/// false.
+pub(crate) fn walk(
+    directory: &Path,
+    depth: u8,
+    filter: PitouFileFilter,
+    visit: &mut dyn FnMut(PitouFile) -> bool,
+) -> bool {
+    if depth == 0 {
+        return true;
+    }
+    let Ok(read_dir) = std::fs::read_dir(directory) else {
+        return true;
+    };
+    for de in read_dir.flatten() {
+        let Ok(metadata) = de.metadata() else {
+            continue;
+        };
+        let file = PitouFile::new(de.path(), metadata);
+        if file.is_dir() && !walk(&de.path(), depth - 1, filter, visit) {
+            return false;
+        }
+        if filter.includes(&file) && !visit(file) {
+            return false;
+        }
+    }
+    true
+}
+
 #[derive(Clone)]
 pub enum SearchType {
     Regex(regex::Regex),
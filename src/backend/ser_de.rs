@@ -1,10 +1,17 @@
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::{path::PathBuf, rc::Rc};
+use std::{path::PathBuf, rc::Rc, time::Duration};
 
 use crate::{
-    msg::SearchMsg, search::SimplifiedSearchOptions, GeneralFolder, PitouDrive, PitouDriveKind,
-    PitouFile, PitouFileFilter, PitouFileMetadata, PitouFilePath, PitouTrashItem,
-    PitouTrashItemMetadata,
+    msg::{
+        ArchiveMsg, CompareEntry, CompareStatus, DeleteMsg, DuplicateGroup, DuplicateMsg,
+        FinishedTransfer, RestoreConflict, RestoreOptions, RestoreOutcome, RestoreResult,
+        ScanState, SearchMsg, SyncStarted, TransferError, TransferMsg, TransferOutcome,
+        TransferPlan, TransferProgress, TransferSessionID, TransferState,
+    },
+    search::SimplifiedSearchOptions,
+    DriveEvent, DriveEventKind, GeneralFolder, PitouDateTime, PitouDrive, PitouDriveFileSystem,
+    PitouDriveKind, PitouFile, PitouFileFilter, PitouFileMetadata, PitouFilePath, PitouFileSize,
+    PitouTrashItem, PitouTrashItemMetadata, TrashQuery, TrashSort, TrashVolumeStats,
 };
 
 const BMS: u8 = b'\\';
@@ -48,6 +55,7 @@ impl Serialize for PitouDrive {
             free_space: u64,
             is_removable: bool,
             kind: PitouDriveKind,
+            filesystem: &'a PitouDriveFileSystem,
         }
 
         PitouDrive {
@@ -57,6 +65,7 @@ impl Serialize for PitouDrive {
             free_space: self.free_space,
             is_removable: self.is_removable,
             kind: self.kind,
+            filesystem: &self.filesystem,
         }
         .serialize(sz)
     }
@@ -215,3 +224,421 @@ impl Serialize for SearchMsg {
         fake_msg.serialize(sz)
     }
 }
+
+impl Serialize for TransferMsg {
+    fn serialize<S: Serializer>(&self, sz: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        enum TransferMsg<'a> {
+            Copy {
+                id: TransferSessionID,
+                state: TransferState,
+                time_elapsed: Duration,
+                progress: &'a TransferProgress,
+                mismatches: &'a Vec<PitouFilePath>,
+                bandwidth_limit: Option<u64>,
+            },
+            Move {
+                id: TransferSessionID,
+                state: TransferState,
+                time_elapsed: Duration,
+                progress: &'a TransferProgress,
+                mismatches: &'a Vec<PitouFilePath>,
+                bandwidth_limit: Option<u64>,
+            },
+        }
+
+        match self {
+            Self::Copy {
+                id,
+                state,
+                time_elapsed,
+                progress,
+                mismatches,
+                bandwidth_limit,
+            } => TransferMsg::Copy {
+                id: *id,
+                state: *state,
+                time_elapsed: *time_elapsed,
+                progress,
+                mismatches,
+                bandwidth_limit: *bandwidth_limit,
+            },
+            Self::Move {
+                id,
+                state,
+                time_elapsed,
+                progress,
+                mismatches,
+                bandwidth_limit,
+            } => TransferMsg::Move {
+                id: *id,
+                state: *state,
+                time_elapsed: *time_elapsed,
+                progress,
+                mismatches,
+                bandwidth_limit: *bandwidth_limit,
+            },
+        }
+        .serialize(sz)
+    }
+}
+
+impl Serialize for TransferPlan {
+    fn serialize<S: Serializer>(&self, sz: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct TransferPlan<'a> {
+            copy: bool,
+            files: u64,
+            dirs: u64,
+            total_bytes: u64,
+            conflicts: &'a Vec<PitouFilePath>,
+            permission_problems: &'a Vec<PitouFilePath>,
+            free_space: Option<u64>,
+            required_space: u64,
+            same_device: bool,
+        }
+
+        TransferPlan {
+            copy: self.copy,
+            files: self.files,
+            dirs: self.dirs,
+            total_bytes: self.total_bytes,
+            conflicts: &self.conflicts,
+            permission_problems: &self.permission_problems,
+            free_space: self.free_space,
+            required_space: self.required_space,
+            same_device: self.same_device,
+        }
+        .serialize(sz)
+    }
+}
+
+impl Serialize for TransferError {
+    fn serialize<S: Serializer>(&self, sz: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct TransferError<'a> {
+            path: &'a PitouFilePath,
+            message: &'a str,
+        }
+
+        TransferError {
+            path: &self.path,
+            message: &self.message,
+        }
+        .serialize(sz)
+    }
+}
+
+impl Serialize for FinishedTransfer {
+    fn serialize<S: Serializer>(&self, sz: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct FinishedTransfer<'a> {
+            id: TransferSessionID,
+            copy: bool,
+            outcome: TransferOutcome,
+            duration: Duration,
+            bytes: u64,
+            files: u64,
+            errors: &'a Vec<TransferError>,
+        }
+
+        FinishedTransfer {
+            id: self.id,
+            copy: self.copy,
+            outcome: self.outcome,
+            duration: self.duration,
+            bytes: self.bytes,
+            files: self.files,
+            errors: &self.errors,
+        }
+        .serialize(sz)
+    }
+}
+
+impl Serialize for DeleteMsg {
+    fn serialize<S: Serializer>(&self, sz: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct DeleteMsg<'a> {
+            id: TransferSessionID,
+            state: TransferState,
+            time_elapsed: Duration,
+            items_done: u64,
+            items_total: u64,
+            failures: &'a Vec<TransferError>,
+            outcome: Option<TransferOutcome>,
+        }
+
+        DeleteMsg {
+            id: self.id,
+            state: self.state,
+            time_elapsed: self.time_elapsed,
+            items_done: self.items_done,
+            items_total: self.items_total,
+            failures: &self.failures,
+            outcome: self.outcome,
+        }
+        .serialize(sz)
+    }
+}
+
+impl<'d> Deserialize<'d> for RestoreOptions {
+    fn deserialize<D: Deserializer<'d>>(dz: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct RestoreOptions {
+            conflict: RestoreConflict,
+            destination: Option<PitouFilePath>,
+        }
+
+        let RestoreOptions {
+            conflict,
+            destination,
+        } = RestoreOptions::deserialize(dz)?;
+        Ok(Self {
+            conflict,
+            destination,
+        })
+    }
+}
+
+impl Serialize for RestoreResult {
+    fn serialize<S: Serializer>(&self, sz: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct RestoreResult<'a> {
+            id: &'a str,
+            outcome: &'a RestoreOutcome,
+            restored_to: Option<&'a PitouFilePath>,
+        }
+
+        RestoreResult {
+            id: &self.id,
+            outcome: &self.outcome,
+            restored_to: self.restored_to.as_ref(),
+        }
+        .serialize(sz)
+    }
+}
+
+impl Serialize for TrashVolumeStats {
+    fn serialize<S: Serializer>(&self, sz: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct TrashVolumeStats<'a> {
+            volume: &'a PitouFilePath,
+            items: u64,
+            size: PitouFileSize,
+            oldest: Option<PitouDateTime>,
+        }
+
+        TrashVolumeStats {
+            volume: &self.volume,
+            items: self.items,
+            size: self.size,
+            oldest: self.oldest,
+        }
+        .serialize(sz)
+    }
+}
+
+impl<'d> Deserialize<'d> for TrashQuery {
+    fn deserialize<D: Deserializer<'d>>(dz: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct TrashQuery {
+            input: String,
+            search_kind: u8,
+            case_sensitive: bool,
+            original_dir: Option<PitouFilePath>,
+            deleted_after: Option<PitouDateTime>,
+            deleted_before: Option<PitouDateTime>,
+            min_size: Option<u64>,
+            max_size: Option<u64>,
+            sort: Option<TrashSort>,
+        }
+
+        let TrashQuery {
+            input,
+            search_kind,
+            case_sensitive,
+            original_dir,
+            deleted_after,
+            deleted_before,
+            min_size,
+            max_size,
+            sort,
+        } = TrashQuery::deserialize(dz)?;
+        Ok(Self {
+            input,
+            search_kind,
+            case_sensitive,
+            original_dir,
+            deleted_after,
+            deleted_before,
+            min_size,
+            max_size,
+            sort,
+        })
+    }
+}
+
+impl Serialize for DriveEvent {
+    fn serialize<S: Serializer>(&self, sz: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct DriveEvent<'a> {
+            kind: DriveEventKind,
+            drive: &'a PitouDrive,
+        }
+
+        DriveEvent {
+            kind: self.kind,
+            drive: &self.drive,
+        }
+        .serialize(sz)
+    }
+}
+
+impl Serialize for DuplicateGroup {
+    fn serialize<S: Serializer>(&self, sz: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct DuplicateGroup<'a> {
+            size: u64,
+            files: &'a Vec<PitouFile>,
+        }
+
+        DuplicateGroup {
+            size: self.size,
+            files: &self.files,
+        }
+        .serialize(sz)
+    }
+}
+
+impl Serialize for DuplicateMsg {
+    fn serialize<S: Serializer>(&self, sz: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct DuplicateMsg<'a> {
+            id: TransferSessionID,
+            state: ScanState,
+            time_elapsed: Duration,
+            files: u64,
+            bytes_hashed: u64,
+            groups_found: u64,
+            wasted_bytes: u64,
+            errors: u64,
+            groups: &'a Vec<DuplicateGroup>,
+        }
+
+        DuplicateMsg {
+            id: self.id,
+            state: self.state,
+            time_elapsed: self.time_elapsed,
+            files: self.files,
+            bytes_hashed: self.bytes_hashed,
+            groups_found: self.groups_found,
+            wasted_bytes: self.wasted_bytes,
+            errors: self.errors,
+            groups: &self.groups,
+        }
+        .serialize(sz)
+    }
+}
+
+impl Serialize for CompareEntry {
+    fn serialize<S: Serializer>(&self, sz: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct CompareEntry<'a> {
+            status: CompareStatus,
+            left: &'a Option<PitouFile>,
+            right: &'a Option<PitouFile>,
+        }
+
+        CompareEntry {
+            status: self.status,
+            left: &self.left,
+            right: &self.right,
+        }
+        .serialize(sz)
+    }
+}
+
+impl Serialize for SyncStarted {
+    fn serialize<S: Serializer>(&self, sz: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct SyncStarted<'a> {
+            transfer: Option<TransferSessionID>,
+            deletion: Option<TransferSessionID>,
+            conflicts: &'a Vec<CompareEntry>,
+        }
+
+        SyncStarted {
+            transfer: self.transfer,
+            deletion: self.deletion,
+            conflicts: &self.conflicts,
+        }
+        .serialize(sz)
+    }
+}
+
+impl Serialize for ArchiveMsg {
+    fn serialize<S: Serializer>(&self, sz: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        enum ArchiveMsg<'a> {
+            Compress {
+                id: TransferSessionID,
+                state: TransferState,
+                time_elapsed: Duration,
+                progress: &'a TransferProgress,
+                archive: &'a PitouFilePath,
+                errors: &'a Vec<TransferError>,
+                outcome: Option<TransferOutcome>,
+            },
+            Extract {
+                id: TransferSessionID,
+                state: TransferState,
+                time_elapsed: Duration,
+                progress: &'a TransferProgress,
+                archive: &'a PitouFilePath,
+                destination: &'a PitouFilePath,
+                errors: &'a Vec<TransferError>,
+                outcome: Option<TransferOutcome>,
+            },
+        }
+
+        match self {
+            Self::Compress {
+                id,
+                state,
+                time_elapsed,
+                progress,
+                archive,
+                errors,
+                outcome,
+            } => ArchiveMsg::Compress {
+                id: *id,
+                state: *state,
+                time_elapsed: *time_elapsed,
+                progress,
+                archive,
+                errors,
+                outcome: *outcome,
+            },
+            Self::Extract {
+                id,
+                state,
+                time_elapsed,
+                progress,
+                archive,
+                destination,
+                errors,
+                outcome,
+            } => ArchiveMsg::Extract {
+                id: *id,
+                state: *state,
+                time_elapsed: *time_elapsed,
+                progress,
+                archive,
+                destination,
+                errors,
+                outcome: *outcome,
+            },
+        }
+        .serialize(sz)
+    }
+}
@@ -0,0 +1,22 @@
+//! Helpers shared by the tests of the backend.
+
+use std::time::{Duration, Instant};
+
+/// How long a test waits on a session before failing.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Polls `read` until what it returns is `done`, and returns that. Panics after [`TIMEOUT`].
+pub(crate) fn wait_for<T>(mut read: impl FnMut() -> T, done: impl Fn(&T) -> bool) -> T {
+    let started = Instant::now();
+    loop {
+        let value = read();
+        if done(&value) {
+            return value;
+        }
+        assert!(
+            started.elapsed() < TIMEOUT,
+            "timed out waiting on a session"
+        );
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
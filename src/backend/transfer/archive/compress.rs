@@ -0,0 +1,350 @@
+//! Writing zip and tar archives.
+
+use std::{
+    fs::{File, Metadata},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::{Datelike, Timelike};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use super::{cancelled, ArchiveConfig};
+use crate::msg::{ArchiveFormat, CompressOptions, CompressionLevel, TransferOutcome};
+
+/// An item to pack, under `name` inside the archive.
+struct Entry {
+    path: PathBuf,
+    name: String,
+    metadata: Metadata,
+}
+
+/// Packs `items` into the archive of `config`, returning how it went.
+pub(super) fn run(
+    config: &ArchiveConfig,
+    items: &[PathBuf],
+    options: CompressOptions,
+) -> TransferOutcome {
+    let archive = &config.archive;
+    let name = archive.file_name().unwrap_or_default().to_string_lossy();
+    let temp = archive.with_file_name(format!(".{name}.part"));
+    if archive.exists() {
+        config.record_error(
+            archive,
+            String::from("an item with the name of the archive exists"),
+        );
+        return TransferOutcome::Failed;
+    }
+
+    let mut entries = Vec::new();
+    for item in items {
+        let name = item.file_name().unwrap_or_default().to_string_lossy();
+        collect(
+            config,
+            item,
+            name.into_owned(),
+            &[archive, &temp],
+            &mut entries,
+        );
+    }
+    config.start_now();
+
+    let res = File::create_new(&temp).and_then(|file| match options.format {
+        ArchiveFormat::Zip => write_zip(config, &entries, file, options.level),
+        ArchiveFormat::Tar => write_tar(config, &entries, file).map(drop),
+        ArchiveFormat::TarGz => {
+            let level = match options.level {
+                CompressionLevel::Fastest => flate2::Compression::fast(),
+                CompressionLevel::Balanced => flate2::Compression::default(),
+                CompressionLevel::Smallest => flate2::Compression::best(),
+            };
+            let encoder = flate2::write::GzEncoder::new(file, level);
+            write_tar(config, &entries, encoder)?.finish().map(drop)
+        }
+        ArchiveFormat::TarZst => {
+            let level = match options.level {
+                CompressionLevel::Fastest => 1,
+                CompressionLevel::Balanced => zstd::DEFAULT_COMPRESSION_LEVEL,
+                CompressionLevel::Smallest => 19,
+            };
+            let encoder = zstd::Encoder::new(file, level)?;
+            write_tar(config, &entries, encoder)?.finish().map(drop)
+        }
+    });
+    let res = res.and_then(|_| match archive.exists() {
+        true => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "an item with the name of the archive showed up meanwhile",
+        )),
+        false => std::fs::rename(&temp, archive),
+    });
+
+    if let Err(e) = res {
+        let _ = std::fs::remove_file(&temp);
+        if config.is_cancelled() {
+            return TransferOutcome::Cancelled;
+        }
+        config.record_error(archive, e.to_string());
+        return TransferOutcome::Failed;
+    }
+    let errors = config.errors.lock().unwrap().len();
+    TransferOutcome::judge(errors, errors, entries.len() + errors)
+}
+
+/// Adds `path` and whatever it holds to `entries`. Items of `skipped`, the archive and its temporary file, are left
+/// out lest an archive end up inside itself.
+fn collect(
+    config: &ArchiveConfig,
+    path: &Path,
+    name: String,
+    skipped: &[&Path],
+    entries: &mut Vec<Entry>,
+) {
+    if skipped.contains(&path) {
+        return;
+    }
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => return config.record_error(path, e.to_string()),
+    };
+    let file_type = metadata.file_type();
+    if file_type.is_file() {
+        config.count_file(metadata.len());
+    } else if !file_type.is_dir() && !file_type.is_symlink() {
+        return config.record_error(path, String::from("special files cannot be archived"));
+    }
+    let is_dir = metadata.is_dir();
+    entries.push(Entry {
+        path: path.to_path_buf(),
+        name: name.clone(),
+        metadata,
+    });
+    if !is_dir {
+        return;
+    }
+    let read_dir = match std::fs::read_dir(path) {
+        Ok(read_dir) => read_dir,
+        Err(e) => return config.record_error(path, e.to_string()),
+    };
+    for entry in read_dir.flatten() {
+        let child = format!("{name}/{}", entry.file_name().to_string_lossy());
+        collect(config, &entry.path(), child, skipped, entries);
+    }
+}
+
+/// Reads a file being packed, reporting progress and stopping once the session is cancelled.
+struct Source<'a> {
+    config: &'a ArchiveConfig,
+    file: io::Take<File>,
+}
+
+impl<'a> Source<'a> {
+    /// `None` after recording the error if the file cannot be opened, in which case it is left out.
+    fn open(config: &'a ArchiveConfig, entry: &Entry) -> Option<Self> {
+        match File::open(&entry.path) {
+            Ok(file) => {
+                let name = entry.path.file_name().unwrap_or_default().to_string_lossy();
+                config.progress.lock().unwrap().start_file(&name);
+                let file = file.take(entry.metadata.len());
+                Some(Self { config, file })
+            }
+            Err(e) => {
+                config.record_error(&entry.path, e.to_string());
+                None
+            }
+        }
+    }
+}
+
+impl Read for Source<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.config.is_cancelled() {
+            return Err(cancelled());
+        }
+        let cnt = self.file.read(buf)?;
+        // archives record the length of a file ahead of its content
+        if cnt == 0 && !buf.is_empty() && self.file.limit() > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "a file shrank while it was being archived",
+            ));
+        }
+        self.config.advance(cnt as u64);
+        Ok(cnt)
+    }
+}
+
+fn write_tar<W: Write>(config: &ArchiveConfig, entries: &[Entry], dst: W) -> io::Result<W> {
+    let mut builder = tar::Builder::new(dst);
+    builder.follow_symlinks(false);
+    for entry in entries {
+        if config.is_cancelled() {
+            return Err(cancelled());
+        }
+        if entry.metadata.is_symlink() {
+            let target = match std::fs::read_link(&entry.path) {
+                Ok(target) => target,
+                // the link went away since it was collected
+                Err(e) => {
+                    config.record_error(&entry.path, e.to_string());
+                    continue;
+                }
+            };
+            let mut header = tar::Header::new_gnu();
+            header.set_metadata(&entry.metadata);
+            header.set_size(0);
+            builder.append_link(&mut header, &entry.name, target)?;
+            continue;
+        }
+        if !entry.metadata.is_file() {
+            builder.append_path_with_name(&entry.path, &entry.name)?;
+            continue;
+        }
+        let Some(source) = Source::open(config, entry) else {
+            continue;
+        };
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata(&entry.metadata);
+        builder.append_data(&mut header, &entry.name, source)?;
+        config.progress.lock().unwrap().finish_file();
+    }
+    builder.into_inner()
+}
+
+fn write_zip(
+    config: &ArchiveConfig,
+    entries: &[Entry],
+    dst: File,
+    level: CompressionLevel,
+) -> io::Result<()> {
+    let level = match level {
+        CompressionLevel::Fastest => 1,
+        CompressionLevel::Balanced => 6,
+        CompressionLevel::Smallest => 9,
+    };
+    let mut zip = ZipWriter::new(dst);
+    for entry in entries {
+        if config.is_cancelled() {
+            return Err(cancelled());
+        }
+        let mut options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(Some(level))
+            .large_file(entry.metadata.len() > u32::MAX as u64);
+        if let Some(time) = entry.metadata.modified().ok().and_then(zip_time) {
+            options = options.last_modified_time(time);
+        }
+        if let Some(mode) = permissions(&entry.metadata) {
+            options = options.unix_permissions(mode);
+        }
+        let file_type = entry.metadata.file_type();
+        if file_type.is_dir() {
+            zip.add_directory(entry.name.as_str(), options)?;
+        } else if file_type.is_symlink() {
+            match std::fs::read_link(&entry.path) {
+                Ok(target) => {
+                    zip.add_symlink(entry.name.as_str(), target.to_string_lossy(), options)?
+                }
+                // the link went away since it was collected
+                Err(e) => config.record_error(&entry.path, e.to_string()),
+            }
+        } else if let Some(source) = Source::open(config, entry) {
+            zip.start_file(entry.name.as_str(), options)?;
+            io::copy(&mut { source }, &mut zip)?;
+            config.progress.lock().unwrap().finish_file();
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// `time` in local time, as zip archives keep it. `None` for times zip cannot hold, before 1980 or after 2107.
+fn zip_time(time: SystemTime) -> Option<zip::DateTime> {
+    let time = chrono::DateTime::<chrono::Local>::from(time).naive_local();
+    zip::DateTime::from_date_and_time(
+        time.year().try_into().ok()?,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .ok()
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn permissions(_: &Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(all(test, unix))]
+mod test_mod {
+    use super::*;
+
+    #[test]
+    fn test_vanished_link() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        std::fs::write(root.join("notes.txt"), b"notes").unwrap();
+        std::os::unix::fs::symlink("notes.txt", root.join("latest")).unwrap();
+        std::os::unix::fs::symlink("notes.txt", root.join("kept")).unwrap();
+        let entries = |config: &ArchiveConfig| {
+            let mut entries = Vec::new();
+            for name in ["kept", "latest", "notes.txt"] {
+                collect(config, &root.join(name), name.into(), &[], &mut entries);
+            }
+            std::fs::remove_file(root.join("latest")).unwrap();
+            entries
+        };
+
+        let config = super::super::add_new_session(root.join("bundle.zip"), None);
+        let entries_zip = entries(&config);
+        write_zip(
+            &config,
+            &entries_zip,
+            File::create(&config.archive).unwrap(),
+            CompressionLevel::Fastest,
+        )
+        .unwrap();
+        assert_eq!(config.errors.lock().unwrap().len(), 1);
+        let zip = zip::ZipArchive::new(File::open(&config.archive).unwrap()).unwrap();
+        let mut names = zip.file_names().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["kept", "notes.txt"]);
+
+        std::os::unix::fs::symlink("notes.txt", root.join("latest")).unwrap();
+        let config = super::super::add_new_session(root.join("bundle.tar"), None);
+        let entries_tar = entries(&config);
+        write_tar(
+            &config,
+            &entries_tar,
+            File::create(&config.archive).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(config.errors.lock().unwrap().len(), 1);
+        let mut tar = tar::Archive::new(File::open(&config.archive).unwrap());
+        let names = tar
+            .entries()
+            .unwrap()
+            .map(|v| {
+                let v = v.unwrap();
+                let link = v.link_name().unwrap().map(|v| v.into_owned());
+                (v.path().unwrap().into_owned(), link)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                (PathBuf::from("kept"), Some(PathBuf::from("notes.txt"))),
+                (PathBuf::from("notes.txt"), None)
+            ]
+        );
+    }
+}
@@ -0,0 +1,475 @@
+//! Extracting zip and tar archives. Every entry is kept inside the destination: names that climb out of it, links
+//! that point out of it and entries that would be written through a link are refused.
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use zip::{result::ZipError, ZipArchive};
+
+use super::{cancelled, ArchiveConfig};
+use crate::msg::{ArchiveFormat, ExtractConflict, ExtractOptions, TransferOutcome};
+
+/// What an entry of an archive makes.
+enum Item {
+    Dir,
+    File,
+    /// a symbolic link to the path given
+    Link(PathBuf),
+    /// another name for the entry given, which tar archives use for hard links
+    HardLink(PathBuf),
+}
+
+/// Extracts the archive of `config` into its destination, returning how it went.
+pub(super) fn run(config: &ArchiveConfig, options: &ExtractOptions) -> TransferOutcome {
+    let archive = &config.archive;
+    let destination = config.destination.as_deref().unwrap_or(Path::new(""));
+    let mut extractor = Extractor {
+        config,
+        destination,
+        conflict: options.conflict,
+        tops: HashMap::new(),
+        entries: 0,
+    };
+    let res = std::fs::create_dir_all(destination)
+        .and_then(|_| format_of(archive))
+        .and_then(|format| match format {
+            ArchiveFormat::Zip => extractor.zip(options.password.as_deref()),
+            format => extractor.tar(format),
+        });
+    if let Err(e) = res {
+        if config.is_cancelled() {
+            return TransferOutcome::Cancelled;
+        }
+        config.record_error(archive, e.to_string());
+        return TransferOutcome::Failed;
+    }
+    let errors = config.errors.lock().unwrap().len();
+    TransferOutcome::judge(errors, errors, extractor.entries.max(errors))
+}
+
+/// The format of `archive`, told from its first bytes.
+fn format_of(archive: &Path) -> io::Result<ArchiveFormat> {
+    let mut magic = Vec::with_capacity(262);
+    File::open(archive)?.take(262).read_to_end(&mut magic)?;
+    if magic.starts_with(b"PK\x03\x04") || magic.starts_with(b"PK\x05\x06") {
+        Ok(ArchiveFormat::Zip)
+    } else if magic.starts_with(&[0x1f, 0x8b]) {
+        Ok(ArchiveFormat::TarGz)
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Ok(ArchiveFormat::TarZst)
+    } else if magic.get(257..262) == Some(b"ustar") {
+        Ok(ArchiveFormat::Tar)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the item is not a zip or tar archive",
+        ))
+    }
+}
+
+fn open_tar(archive: &Path, format: ArchiveFormat) -> io::Result<tar::Archive<Box<dyn Read>>> {
+    let file = BufReader::new(File::open(archive)?);
+    let reader: Box<dyn Read> = match format {
+        ArchiveFormat::TarGz => Box::new(flate2::bufread::GzDecoder::new(file)),
+        ArchiveFormat::TarZst => Box::new(zstd::Decoder::with_buffer(file)?),
+        _ => Box::new(file),
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+struct Extractor<'a> {
+    config: &'a ArchiveConfig,
+    destination: &'a Path,
+    conflict: ExtractConflict,
+    /// where each item at the top of the archive goes, `None` for those skipped over a conflict
+    tops: HashMap<OsString, Option<PathBuf>>,
+    /// the entries met so far
+    entries: usize,
+}
+
+impl Extractor<'_> {
+    fn zip(&mut self, password: Option<&str>) -> io::Result<()> {
+        let file = BufReader::new(File::open(&self.config.archive)?);
+        let mut zip = ZipArchive::new(file)?;
+        for i in 0..zip.len() {
+            let entry = zip.by_index_raw(i)?;
+            if entry.is_file() {
+                self.config.count_file(entry.size());
+            }
+        }
+        self.config.start_now();
+
+        for i in 0..zip.len() {
+            if self.config.is_cancelled() {
+                return Err(cancelled());
+            }
+            let name = PathBuf::from(zip.name_for_index(i).unwrap_or_default());
+            let entry = match password {
+                Some(password) => zip.by_index_decrypt(i, password.as_bytes()),
+                None => zip.by_index(i),
+            };
+            let mut entry = match entry {
+                Ok(entry) => entry,
+                Err(ZipError::InvalidPassword) => {
+                    return Err(io::Error::other("the password is wrong"))
+                }
+                Err(ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED)) => {
+                    return Err(io::Error::other("the archive needs a password"))
+                }
+                Err(e) => {
+                    self.record_error(&name, e.to_string());
+                    continue;
+                }
+            };
+            let item = if entry.is_dir() {
+                Item::Dir
+            } else if entry.is_symlink() {
+                let mut target = String::new();
+                match entry.by_ref().take(4096).read_to_string(&mut target) {
+                    Ok(_) => Item::Link(PathBuf::from(target)),
+                    Err(e) => {
+                        self.record_error(&name, e.to_string());
+                        continue;
+                    }
+                }
+            } else {
+                Item::File
+            };
+            let modified = entry.last_modified().and_then(system_time);
+            let mode = entry.unix_mode();
+            self.extract(&name, item, &mut entry, mode, modified)?;
+        }
+        Ok(())
+    }
+
+    fn tar(&mut self, format: ArchiveFormat) -> io::Result<()> {
+        // tar archives keep no index, so sizing them up takes a pass of its own
+        for entry in open_tar(&self.config.archive, format)?.entries()? {
+            if self.config.is_cancelled() {
+                return Err(cancelled());
+            }
+            let entry = entry?;
+            if entry.header().entry_type().is_file() {
+                self.config.count_file(entry.size());
+            }
+        }
+        self.config.start_now();
+
+        let mut archive = open_tar(&self.config.archive, format)?;
+        for entry in archive.entries()? {
+            if self.config.is_cancelled() {
+                return Err(cancelled());
+            }
+            let mut entry = entry?;
+            let name = entry.path()?.into_owned();
+            let header = entry.header();
+            let (mode, modified) = (header.mode().ok(), header.mtime().ok());
+            let entry_type = header.entry_type();
+            let item = if entry_type.is_dir() {
+                Item::Dir
+            } else if entry_type.is_file() {
+                Item::File
+            } else if entry_type.is_symlink() || entry_type.is_hard_link() {
+                let Some(target) = entry.link_name()? else {
+                    self.record_error(&name, String::from("the link has no target"));
+                    continue;
+                };
+                match entry_type.is_symlink() {
+                    true => Item::Link(target.into_owned()),
+                    false => Item::HardLink(target.into_owned()),
+                }
+            } else if entry_type.is_pax_global_extensions() || entry_type.is_pax_local_extensions()
+            {
+                continue;
+            } else {
+                self.record_error(&name, String::from("special files are not extracted"));
+                continue;
+            };
+            let modified = modified.map(|v| SystemTime::UNIX_EPOCH + Duration::from_secs(v));
+            self.extract(&name, item, &mut entry, mode, modified)?;
+        }
+        Ok(())
+    }
+
+    /// Extracts the entry `name` of the archive, reading the content of files from `content`. Problems with the entry
+    /// itself are recorded, so an error means the session cannot go on.
+    fn extract(
+        &mut self,
+        name: &Path,
+        item: Item,
+        content: &mut dyn Read,
+        mode: Option<u32>,
+        modified: Option<SystemTime>,
+    ) -> io::Result<()> {
+        self.entries += 1;
+        let res = self.target(name).and_then(|target| {
+            let Some(target) = target else {
+                return Ok(());
+            };
+            self.make_parents(&target)?;
+            match item {
+                Item::Dir => make_dir(&target),
+                Item::File => self.write_file(&target, content, mode, modified),
+                Item::Link(link) => {
+                    if escapes(name, &link) {
+                        return Err(refused("the link points outside the destination"));
+                    }
+                    remove_in_the_way(&target)?;
+                    symlink(&link, &target)
+                }
+                Item::HardLink(original) => {
+                    let Some(original) = self.target(&original)? else {
+                        return Ok(());
+                    };
+                    // the entry linked to is held to the same rules as the ones written
+                    self.make_parents(&original)?;
+                    remove_in_the_way(&target)?;
+                    std::fs::hard_link(original, &target)
+                }
+            }
+        });
+        match res {
+            Err(_) if self.config.is_cancelled() => Err(cancelled()),
+            Err(e) => {
+                self.record_error(name, e.to_string());
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /// Where the entry `name` goes, `None` if it is skipped over a conflict. Names that are absolute or that climb
+    /// up a level are refused, even when they would come back down inside the destination.
+    fn target(&mut self, name: &Path) -> io::Result<Option<PathBuf>> {
+        let mut components = Vec::new();
+        for component in name.components() {
+            match component {
+                Component::Normal(component) => components.push(component),
+                Component::CurDir => (),
+                _ => return Err(refused("the entry points outside the destination")),
+            }
+        }
+        let Some((top, rest)) = components.split_first() else {
+            return Err(refused("the entry has no name"));
+        };
+        let top = self
+            .tops
+            .entry(top.to_os_string())
+            .or_insert_with(|| {
+                let path = self.destination.join(top);
+                match std::fs::symlink_metadata(&path) {
+                    Err(_) => Some(path),
+                    Ok(_) => match self.conflict {
+                        ExtractConflict::Rename => Some(free_name(&path)),
+                        ExtractConflict::Overwrite => Some(path),
+                        ExtractConflict::Skip => None,
+                    },
+                }
+            })
+            .clone();
+        let target = top.map(|top| rest.iter().fold(top, |path, v| path.join(v)));
+        if target.as_ref() == Some(&self.config.archive) {
+            return Err(refused("the entry would replace the archive"));
+        }
+        Ok(target)
+    }
+
+    /// Creates the folders `target` goes in that are missing, refusing to go through links or other files.
+    fn make_parents(&self, target: &Path) -> io::Result<()> {
+        let parent = target.parent().unwrap_or(self.destination);
+        let relative = parent
+            .strip_prefix(self.destination)
+            .unwrap_or(Path::new(""));
+        let mut path = self.destination.to_path_buf();
+        for component in relative.components() {
+            path.push(component);
+            match std::fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.is_dir() => (),
+                Ok(metadata) if metadata.is_symlink() => {
+                    return Err(refused("a link is in the way of the entry"))
+                }
+                Ok(_) => return Err(refused("a file is in the way of the entry")),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => std::fs::create_dir(&path)?,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a file under a temporary name beside `target`, which it only takes once complete. A file or link
+    /// already at `target` is replaced rather than written through.
+    fn write_file(
+        &self,
+        target: &Path,
+        content: &mut dyn Read,
+        mode: Option<u32>,
+        modified: Option<SystemTime>,
+    ) -> io::Result<()> {
+        if std::fs::symlink_metadata(target).is_ok_and(|v| v.is_dir()) {
+            return Err(refused("a folder is in the way of the entry"));
+        }
+        let name = target.file_name().unwrap_or_default().to_string_lossy();
+        let temp = target.with_file_name(format!(".{name}.part"));
+        let _ = std::fs::remove_file(&temp);
+        let mut progress = self.config.progress.lock().unwrap();
+        progress.start_file(&name);
+        std::mem::drop(progress);
+
+        let res = File::create_new(&temp).and_then(|file| {
+            let mut sink = Sink {
+                config: self.config,
+                file,
+            };
+            io::copy(content, &mut sink)?;
+            let file = sink.file;
+            if let Some(mode) = mode {
+                set_permissions(&file, mode)?;
+            }
+            if let Some(modified) = modified {
+                file.set_modified(modified)?;
+            }
+            std::fs::rename(&temp, target)
+        });
+        if res.is_err() {
+            let _ = std::fs::remove_file(&temp);
+        }
+        res?;
+        self.config.progress.lock().unwrap().finish_file();
+        Ok(())
+    }
+
+    fn record_error(&self, name: &Path, message: String) {
+        self.config
+            .record_error(&self.destination.join(name), message)
+    }
+}
+
+/// Whether the link `name` of the archive, pointing to `link`, leads outside of the folder the archive is extracted to.
+///
+/// Only the folders above the link are known to be real ones, so `..` is only allowed at the start of `link`. Past a
+/// named component it could be climbing out of another link of the archive.
+fn escapes(name: &Path, link: &Path) -> bool {
+    // a leading `./` of the name takes it no deeper
+    let normal = name
+        .components()
+        .filter(|v| matches!(v, Component::Normal(_)));
+    let mut depth = normal.count().saturating_sub(1);
+    let mut descended = false;
+    for component in link.components() {
+        match component {
+            Component::Normal(_) => descended = true,
+            Component::CurDir => (),
+            Component::ParentDir if depth > 0 && !descended => depth -= 1,
+            _ => return true,
+        }
+    }
+    false
+}
+
+fn make_dir(target: &Path) -> io::Result<()> {
+    match std::fs::symlink_metadata(target) {
+        Ok(metadata) if metadata.is_dir() => Ok(()),
+        Ok(_) => Err(refused("an item is in the way of the folder")),
+        Err(_) => std::fs::create_dir(target),
+    }
+}
+
+/// Removes the file or link at `target` for a link to take its place. Folders are left alone.
+fn remove_in_the_way(target: &Path) -> io::Result<()> {
+    match std::fs::symlink_metadata(target) {
+        Ok(metadata) if metadata.is_dir() => Err(refused("a folder is in the way of the entry")),
+        Ok(_) => std::fs::remove_file(target),
+        Err(_) => Ok(()),
+    }
+}
+
+fn refused(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, message)
+}
+
+/// The first of `name (2).ext`, `name (3).ext`, ... next to `path` that nothing occupies.
+fn free_name(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default();
+    let extension = path.extension();
+    for n in 2.. {
+        let mut name = stem.to_os_string();
+        name.push(format!(" ({n})"));
+        if let Some(extension) = extension {
+            name.push(".");
+            name.push(extension);
+        }
+        let candidate = path.with_file_name(name);
+        if std::fs::symlink_metadata(&candidate).is_err() {
+            return candidate;
+        }
+    }
+    unreachable!()
+}
+
+/// `time`, in local time as zip archives keep it. `None` for times that do not exist.
+fn system_time(time: zip::DateTime) -> Option<SystemTime> {
+    let date = chrono::NaiveDate::from_ymd_opt(
+        time.year().into(),
+        time.month().into(),
+        time.day().into(),
+    )?;
+    let time = date.and_hms_opt(
+        time.hour().into(),
+        time.minute().into(),
+        time.second().into(),
+    )?;
+    let time = time.and_local_timezone(chrono::Local).earliest()?;
+    Some(time.into())
+}
+
+/// Writes an extracted file, reporting progress and stopping once the session is cancelled.
+struct Sink<'a> {
+    config: &'a ArchiveConfig,
+    file: File,
+}
+
+impl Write for Sink<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.config.is_cancelled() {
+            return Err(cancelled());
+        }
+        let cnt = self.file.write(buf)?;
+        self.config.advance(cnt as u64);
+        Ok(cnt)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Sets the permission bits of `mode` on `file`, leaving out those that could raise privileges.
+#[cfg(unix)]
+fn set_permissions(file: &File, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(std::fs::Permissions::from_mode(mode & 0o777))
+}
+
+#[cfg(not(unix))]
+fn set_permissions(_: &File, _: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(link: &Path, target: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(link, target)
+}
+
+#[cfg(not(unix))]
+fn symlink(_: &Path, _: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "links are only extracted on unix",
+    ))
+}
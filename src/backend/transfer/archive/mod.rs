@@ -0,0 +1,514 @@
+//! Archive sessions, which pack items into zip and tar archives or extract them. Each runs on a thread of its own and
+//! is followed through an id, reporting its progress like a transfer.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Instant,
+};
+
+use super::tracker::ProgressTracker;
+use crate::{
+    collections::Registry,
+    msg::{
+        ArchiveMsg, CompressOptions, ExtractOptions, TransferError, TransferOutcome,
+        TransferSessionID, TransferSize, TransferState,
+    },
+    PitouFile, PitouFilePath,
+};
+
+mod compress;
+mod extract;
+
+struct ArchiveConfig {
+    id: TransferSessionID,
+    /// the archive written or read
+    archive: PathBuf,
+    /// the folder items are extracted to, for extractions
+    destination: Option<PathBuf>,
+    state: Mutex<TransferState>,
+    started: Mutex<Instant>,
+    progress: Mutex<ProgressTracker>,
+    cancelled: AtomicBool,
+    errors: Mutex<Vec<TransferError>>,
+    outcome: Mutex<Option<TransferOutcome>>,
+}
+
+impl ArchiveConfig {
+    fn is_ongoing(&self) -> bool {
+        !matches!(*self.state.lock().unwrap(), TransferState::Terminated(_))
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Counts a file of `len` bytes into the total, while the items are being sized up.
+    fn count_file(&self, len: u64) {
+        self.state.lock().unwrap().append_total(len);
+        self.progress.lock().unwrap().count_file();
+    }
+
+    fn start_now(&self) {
+        self.state.lock().unwrap().end_init();
+        *self.started.lock().unwrap() = Instant::now();
+        self.progress.lock().unwrap().record(0);
+    }
+
+    fn advance(&self, val: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.append_current(val);
+        if let TransferState::Active(TransferSize { total: _, current }) = *state {
+            std::mem::drop(state);
+            self.progress.lock().unwrap().record(current);
+        }
+    }
+
+    fn record_error(&self, path: &Path, message: String) {
+        let path = PitouFilePath::from_pathbuf(path.to_path_buf());
+        self.errors
+            .lock()
+            .unwrap()
+            .push(TransferError { path, message });
+    }
+
+    fn terminate_now(&self, outcome: TransferOutcome) {
+        *self.outcome.lock().unwrap() = Some(outcome);
+        let mut state = self.state.lock().unwrap();
+        *state = match *state {
+            TransferState::Initializing(total) => {
+                TransferState::Terminated(TransferSize { total, current: 0 })
+            }
+            TransferState::Active(size) | TransferState::Terminated(size) => {
+                TransferState::Terminated(size)
+            }
+        }
+    }
+
+    fn read(&self) -> ArchiveMsg {
+        let state = *self.state.lock().unwrap();
+        let time_elapsed = self.started.lock().unwrap().elapsed();
+        let size = match state {
+            TransferState::Initializing(_) => None,
+            TransferState::Active(size) | TransferState::Terminated(size) => Some(size),
+        };
+        let id = self.id;
+        let progress = self.progress.lock().unwrap().read(size, time_elapsed);
+        let archive = PitouFilePath::from_pathbuf(self.archive.clone());
+        let errors = self.errors.lock().unwrap().clone();
+        let outcome = *self.outcome.lock().unwrap();
+        match &self.destination {
+            None => ArchiveMsg::Compress {
+                id,
+                state,
+                time_elapsed,
+                progress,
+                archive,
+                errors,
+                outcome,
+            },
+            Some(destination) => ArchiveMsg::Extract {
+                id,
+                state,
+                time_elapsed,
+                progress,
+                archive,
+                destination: PitouFilePath::from_pathbuf(destination.clone()),
+                errors,
+                outcome,
+            },
+        }
+    }
+}
+
+fn cancelled() -> io::Error {
+    io::Error::other("the session was cancelled")
+}
+
+type ArchiveSessions = Mutex<Registry<Arc<ArchiveConfig>>>;
+static ARCHIVE_SESSIONS: OnceLock<ArchiveSessions> = OnceLock::new();
+
+fn get_sessions() -> &'static ArchiveSessions {
+    ARCHIVE_SESSIONS.get_or_init(|| Mutex::new(Registry::new()))
+}
+
+fn add_new_session(archive: PathBuf, destination: Option<PathBuf>) -> Arc<ArchiveConfig> {
+    let mut sessions = get_sessions().lock().unwrap();
+    let key = sessions.insert_with(|key| {
+        Arc::new(ArchiveConfig {
+            id: key.into(),
+            archive,
+            destination,
+            state: Mutex::new(TransferState::Initializing(0)),
+            started: Mutex::new(Instant::now()),
+            progress: Mutex::new(ProgressTracker::new()),
+            cancelled: AtomicBool::new(false),
+            errors: Mutex::new(Vec::new()),
+            outcome: Mutex::new(None),
+        })
+    });
+    sessions.get(key).unwrap().clone()
+}
+
+/// Starts packing `items` into a new archive at `archive`. The archive is written under a temporary name beside it and
+/// only takes its own once complete; an archive already there is never replaced.
+pub fn compress(
+    items: Vec<PitouFile>,
+    archive: PitouFilePath,
+    options: CompressOptions,
+) -> TransferSessionID {
+    let config = add_new_session(archive.path, None);
+    let id = config.id;
+    std::thread::spawn(move || {
+        let items = items.into_iter().map(|v| v.path.path).collect::<Vec<_>>();
+        let outcome = compress::run(&config, &items, options);
+        config.terminate_now(outcome)
+    });
+    id
+}
+
+/// Starts extracting the zip or tar archive `archive` into the folder holding it. The format is told from the content
+/// of the archive rather than its name.
+pub fn extract_here(archive: PitouFilePath, options: ExtractOptions) -> TransferSessionID {
+    let destination = archive.path.parent().unwrap_or(Path::new("")).to_path_buf();
+    extract_to(archive, PitouFilePath::from_pathbuf(destination), options)
+}
+
+/// Starts extracting the zip or tar archive `archive` into `destination`, which is created if need be. Entries that
+/// would land outside of it, through their names or through links, are refused and reported.
+pub fn extract_to(
+    archive: PitouFilePath,
+    destination: PitouFilePath,
+    options: ExtractOptions,
+) -> TransferSessionID {
+    let config = add_new_session(archive.path, Some(destination.path));
+    let id = config.id;
+    std::thread::spawn(move || {
+        let outcome = extract::run(&config, &options);
+        config.terminate_now(outcome)
+    });
+    id
+}
+
+/// Stops the session with `id`. A compression discards what it wrote, while an extraction keeps the items it is done
+/// with. Returns false if there is no such session or it is already over.
+pub fn cancel(id: TransferSessionID) -> bool {
+    match get_sessions().lock().unwrap().get(id.into()) {
+        Some(config) if config.is_ongoing() => {
+            config.cancelled.store(true, Ordering::Relaxed);
+            true
+        }
+        _ => false,
+    }
+}
+
+pub fn get_all_active_sessions() -> Vec<ArchiveMsg> {
+    get_sessions()
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(_, v)| if v.is_ongoing() { Some(v.read()) } else { None })
+        .collect()
+}
+
+pub fn get_session_with_id(id: TransferSessionID) -> Option<ArchiveMsg> {
+    let config = get_sessions().lock().unwrap().get(id.into()).cloned();
+    config.map(|v| v.read())
+}
+
+pub fn clean_dead_sessions() {
+    get_sessions().lock().unwrap().retain(|_, v| v.is_ongoing());
+}
+
+#[cfg(test)]
+mod test_mod {
+    use std::io::Read;
+
+    use super::*;
+    use crate::{
+        backend::testing,
+        msg::{ArchiveFormat, CompressionLevel, ExtractConflict},
+    };
+
+    fn wait_for(id: TransferSessionID) -> ArchiveMsg {
+        testing::wait_for(
+            || get_session_with_id(id).unwrap(),
+            ArchiveMsg::is_terminated,
+        )
+    }
+
+    /// The files of the tar archive `archive` with their contents, sorted by name.
+    fn read_tar(archive: impl Read) -> Vec<(String, Vec<u8>)> {
+        let mut archive = tar::Archive::new(archive);
+        let mut res = archive
+            .entries()
+            .unwrap()
+            .map(|v| {
+                let mut v = v.unwrap();
+                let name = v.path().unwrap().to_string_lossy().into_owned();
+                let mut content = Vec::new();
+                v.read_to_end(&mut content).unwrap();
+                (name, content)
+            })
+            .collect::<Vec<_>>();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn test_compress() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let project = root.join("project");
+        std::fs::create_dir_all(project.join("src")).unwrap();
+        std::fs::write(project.join("src").join("main.rs"), b"fn main() {}").unwrap();
+        std::fs::write(root.join("notes.txt"), vec![b'n'; 10_000]).unwrap();
+        let items = || {
+            [
+                project.clone(),
+                root.join("notes.txt"),
+                root.join("missing"),
+            ]
+            .into_iter()
+            .map(|v| PitouFile::without_metadata(PitouFilePath::from_pathbuf(v)))
+            .collect::<Vec<_>>()
+        };
+        let expected = vec![
+            (String::from("notes.txt"), vec![b'n'; 10_000]),
+            (String::from("project"), vec![]),
+            (String::from("project/src"), vec![]),
+            (
+                String::from("project/src/main.rs"),
+                b"fn main() {}".to_vec(),
+            ),
+        ];
+
+        for format in [
+            ArchiveFormat::Tar,
+            ArchiveFormat::TarGz,
+            ArchiveFormat::TarZst,
+        ] {
+            let archive = root.join(format!("bundle.{}", format.extension()));
+            let options = CompressOptions {
+                format,
+                level: CompressionLevel::Smallest,
+            };
+            let path = PitouFilePath::from_pathbuf(archive.clone());
+            let msg = wait_for(compress(items(), path, options));
+            assert_eq!(msg.outcome(), Some(TransferOutcome::CompletedWithErrors));
+            assert_eq!(msg.errors().len(), 1);
+            assert_eq!(msg.progress().files_done, 2);
+            let file = std::fs::File::open(&archive).unwrap();
+            let entries = match format {
+                ArchiveFormat::TarGz => read_tar(flate2::read::GzDecoder::new(file)),
+                ArchiveFormat::TarZst => read_tar(zstd::Decoder::new(file).unwrap()),
+                _ => read_tar(file),
+            };
+            let entries = entries
+                .into_iter()
+                .map(|(name, content)| (name.trim_end_matches('/').to_owned(), content))
+                .collect::<Vec<_>>();
+            assert_eq!(entries, expected);
+        }
+
+        // packing the folder an archive goes to leaves the archive out
+        let archive = project.join("project.zip");
+        let options = CompressOptions {
+            format: ArchiveFormat::Zip,
+            level: CompressionLevel::Balanced,
+        };
+        let path = || PitouFilePath::from_pathbuf(archive.clone());
+        let project_item = || {
+            vec![PitouFile::without_metadata(PitouFilePath::from_pathbuf(
+                project.clone(),
+            ))]
+        };
+        let msg = wait_for(compress(project_item(), path(), options));
+        assert_eq!(msg.outcome(), Some(TransferOutcome::Completed));
+        let mut zip = zip::ZipArchive::new(std::fs::File::open(&archive).unwrap()).unwrap();
+        let mut names = zip.file_names().map(String::from).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["project/", "project/src/", "project/src/main.rs"]);
+        let mut content = String::new();
+        zip.by_name("project/src/main.rs")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "fn main() {}");
+
+        // an archive already there is left alone
+        let msg = wait_for(compress(project_item(), path(), options));
+        assert_eq!(msg.outcome(), Some(TransferOutcome::Failed));
+        assert!(zip::ZipArchive::new(std::fs::File::open(&archive).unwrap()).is_ok());
+        assert!(!project.join(".project.zip.part").exists());
+    }
+
+    /// Appends an entry to `builder` without the checks of `tar` on its name, as a hostile archive would.
+    fn append_raw<W: std::io::Write>(
+        builder: &mut tar::Builder<W>,
+        name: &str,
+        kind: tar::EntryType,
+        link: Option<&str>,
+        content: &[u8],
+    ) {
+        let mut header = tar::Header::new_gnu();
+        header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_entry_type(kind);
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        if let Some(link) = link {
+            header.set_link_name(link).unwrap();
+        }
+        header.set_cksum();
+        builder.append(&header, content).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_extract() {
+        use tar::EntryType;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let archive = root.join("hostile.tar.gz");
+        let file = std::fs::File::create(&archive).unwrap();
+        let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::fast());
+        let mut builder = tar::Builder::new(encoder);
+        append_raw(&mut builder, "pkg/", EntryType::Directory, None, b"");
+        append_raw(
+            &mut builder,
+            "pkg/a.txt",
+            EntryType::Regular,
+            None,
+            b"alpha",
+        );
+        append_raw(
+            &mut builder,
+            "../evil.txt",
+            EntryType::Regular,
+            None,
+            b"evil",
+        );
+        let symlink = EntryType::Symlink;
+        append_raw(&mut builder, "pkg/up", symlink, Some("../../outside"), b"");
+        append_raw(&mut builder, "pkg/abs", symlink, Some("/etc"), b"");
+        append_raw(&mut builder, "pkg/sib", symlink, Some("../pkg/a.txt"), b"");
+        append_raw(&mut builder, "./pkg/up", symlink, Some("../x"), b"");
+        append_raw(&mut builder, "./pkg/deep", symlink, Some("../../x"), b"");
+        append_raw(&mut builder, "./up", symlink, Some("../x"), b"");
+        // `a/..` is the folder above the destination once `a` is a link to it
+        append_raw(&mut builder, "a", symlink, Some("."), b"");
+        append_raw(&mut builder, "b/", EntryType::Directory, None, b"");
+        append_raw(&mut builder, "b/c", symlink, Some("../a/.."), b"");
+        append_raw(&mut builder, "pkg/sub/", EntryType::Directory, None, b"");
+        append_raw(&mut builder, "pkg/door", symlink, Some("sub"), b"");
+        append_raw(
+            &mut builder,
+            "pkg/door/b.txt",
+            EntryType::Regular,
+            None,
+            b"b",
+        );
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let out = root.join("out");
+        let extract = |conflict| {
+            let options = ExtractOptions {
+                conflict,
+                password: None,
+            };
+            let path = PitouFilePath::from_pathbuf(archive.clone());
+            let destination = PitouFilePath::from_pathbuf(out.clone());
+            wait_for(extract_to(path, destination, options))
+        };
+        let msg = extract(ExtractConflict::Rename);
+        assert_eq!(msg.outcome(), Some(TransferOutcome::CompletedWithErrors));
+        let mut refused = msg
+            .errors()
+            .iter()
+            .map(|v| v.path.path.strip_prefix(&out).unwrap().to_path_buf())
+            .collect::<Vec<_>>();
+        refused.sort();
+        let expected = [
+            "../evil.txt",
+            "b/c",
+            "pkg/abs",
+            "pkg/deep",
+            "pkg/door/b.txt",
+            "pkg/up",
+            "up",
+        ];
+        assert_eq!(refused, expected.map(PathBuf::from));
+        assert_eq!(msg.progress().files_done, 1);
+        assert!(!root.join("evil.txt").exists());
+        assert!(std::fs::symlink_metadata(out.join("b").join("c")).is_err());
+        assert!(!out.join("pkg").join("sub").join("b.txt").exists());
+        assert_eq!(
+            std::fs::read(out.join("pkg").join("sib")).unwrap(),
+            b"alpha"
+        );
+        let up = std::fs::read_link(out.join("pkg").join("up")).unwrap();
+        assert!(up == Path::new("../x"));
+
+        // conflicts are settled once for each item at the top of the archive
+        assert!(extract(ExtractConflict::Rename).outcome().is_some());
+        assert_eq!(
+            std::fs::read(out.join("pkg (2)").join("a.txt")).unwrap(),
+            b"alpha"
+        );
+        std::fs::write(out.join("pkg").join("a.txt"), b"edited").unwrap();
+        // only the entries refused outright are reported
+        let skipped = extract(ExtractConflict::Skip);
+        let mut refused = skipped
+            .errors()
+            .iter()
+            .map(|v| &v.path.path)
+            .collect::<Vec<_>>();
+        refused.sort();
+        assert!(refused == [&out.join("../evil.txt"), &out.join("up")]);
+        assert_eq!(
+            std::fs::read(out.join("pkg").join("a.txt")).unwrap(),
+            b"edited"
+        );
+        assert!(!out.join("pkg (3)").exists());
+        extract(ExtractConflict::Overwrite);
+        assert_eq!(
+            std::fs::read(out.join("pkg").join("a.txt")).unwrap(),
+            b"alpha"
+        );
+
+        // encrypted zip archives, extracted beside themselves
+        let zipped = root.join("zipped");
+        std::fs::create_dir_all(&zipped).unwrap();
+        let archive = zipped.join("secret.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&archive).unwrap());
+        let options = zip::write::SimpleFileOptions::default()
+            .with_aes_encryption(zip::AesMode::Aes256, "hunter2");
+        zip.start_file("secret.txt", options).unwrap();
+        std::io::Write::write_all(&mut zip, b"the plans").unwrap();
+        zip.finish().unwrap();
+        let extract = |password: Option<&str>| {
+            let options = ExtractOptions {
+                conflict: ExtractConflict::Rename,
+                password: password.map(String::from),
+            };
+            let path = PitouFilePath::from_pathbuf(archive.clone());
+            wait_for(extract_here(path, options))
+        };
+        for password in [None, Some("hunter3")] {
+            let msg = extract(password);
+            assert_eq!(msg.outcome(), Some(TransferOutcome::Failed));
+            assert!(!zipped.join("secret.txt").exists());
+        }
+        let msg = extract(Some("hunter2"));
+        assert_eq!(msg.outcome(), Some(TransferOutcome::Completed));
+        assert_eq!(
+            std::fs::read(zipped.join("secret.txt")).unwrap(),
+            b"the plans"
+        );
+    }
+}
@@ -0,0 +1,496 @@
+//! In-place updates of files that already exist at the destination, in the manner of rsync: blocks of the source that
+//! the destination already holds are taken from the destination, wherever they are in it, and only the rest is
+//! written from the source.
+//!
+//! The destination is split into blocks, each summed with a weak checksum that can be rolled one byte at a time and a
+//! strong hash. The source is then scanned with the rolling checksum, so that a block is found again after data was
+//! inserted or removed before it. Blocks found at their own offset are not written at all. Blocks found elsewhere are
+//! copied within the destination, in an order that reads every one of them before it is overwritten; one that cannot
+//! be ordered that way is written from the source instead.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+use super::engine::Progress;
+
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// Makes `dst` identical to `src`, reusing the blocks of `dst` that also appear in `src`. Returns the number of bytes
+/// that were taken from `dst` rather than from `src`.
+///
+/// Unlike a regular copy this modifies `dst` directly, so a failure part way leaves it holding a mix of both versions.
+pub(super) fn update<F: FnMut(u64)>(
+    src: &File,
+    dst: &File,
+    progress: &mut Progress<F>,
+) -> io::Result<u64> {
+    let signature = Signature::of(dst)?;
+    let ops = scan(src, &signature)?;
+    apply(src, dst, ops, progress)
+}
+
+/// Where each part of the source comes from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    /// already in the destination at the same offset
+    Same { at: u64, len: u64 },
+    /// in the destination at `from`, to be copied to `to`
+    Moved { from: u64, to: u64, len: u64 },
+    /// only in the source
+    Literal { at: u64, len: u64 },
+}
+
+/// Appends `op` to `ops`, extending the last one when they are contiguous.
+fn push(ops: &mut Vec<Op>, op: Op) {
+    let merged = match (ops.last_mut(), op) {
+        (_, Op::Same { len: 0, .. } | Op::Literal { len: 0, .. }) => return,
+        (
+            Some(Op::Same { at, len }),
+            Op::Same {
+                at: next,
+                len: more,
+            },
+        )
+        | (
+            Some(Op::Literal { at, len }),
+            Op::Literal {
+                at: next,
+                len: more,
+            },
+        ) if *at + *len == next => {
+            *len += more;
+            true
+        }
+        (
+            Some(Op::Moved { from, to, len }),
+            Op::Moved {
+                from: next_from,
+                to: next_to,
+                len: more,
+            },
+        ) if *from + *len == next_from && *to + *len == next_to => {
+            *len += more;
+            true
+        }
+        _ => false,
+    };
+    if !merged {
+        ops.push(op)
+    }
+}
+
+/// The checksum of rsync over a window of [`BLOCK_SIZE`] bytes, which can be moved along by one byte.
+struct Rolling {
+    a: u32,
+    b: u32,
+}
+
+impl Rolling {
+    fn new(block: &[u8]) -> Self {
+        let (mut a, mut b) = (0u32, 0u32);
+        for (idx, &byte) in block.iter().enumerate() {
+            a = a.wrapping_add(byte as u32);
+            b = b.wrapping_add((block.len() - idx) as u32 * byte as u32);
+        }
+        Self { a, b }
+    }
+
+    /// Moves the window past `out`, taking in `new` at its end.
+    fn roll(&mut self, out: u8, new: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(new as u32);
+        self.b = self
+            .b
+            .wrapping_sub(BLOCK_SIZE as u32 * out as u32)
+            .wrapping_add(self.a);
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+struct Block {
+    weak: u32,
+    strong: blake3::Hash,
+}
+
+/// The checksums of the blocks of a destination file.
+struct Signature {
+    blocks: Vec<Block>,
+    /// block indices by weak checksum
+    lookup: HashMap<u32, Vec<usize>>,
+    /// the part at the end too short for a block, as its length and hash
+    tail: Option<(u64, blake3::Hash)>,
+}
+
+impl Signature {
+    fn of(mut file: &File) -> io::Result<Self> {
+        file.seek(SeekFrom::Start(0))?;
+        let (mut blocks, mut lookup, mut tail) = (Vec::new(), HashMap::new(), None);
+        let mut buffer = vec![0; BLOCK_SIZE];
+        loop {
+            let cnt = read_full(file, &mut buffer)?;
+            if cnt < BLOCK_SIZE {
+                if cnt > 0 {
+                    tail = Some((cnt as u64, blake3::hash(&buffer[..cnt])));
+                }
+                break;
+            }
+            let weak = Rolling::new(&buffer).digest();
+            lookup
+                .entry(weak)
+                .or_insert_with(Vec::new)
+                .push(blocks.len());
+            blocks.push(Block {
+                weak,
+                strong: blake3::hash(&buffer),
+            });
+        }
+        Ok(Self {
+            blocks,
+            lookup,
+            tail,
+        })
+    }
+
+    /// The block that `window`, found at `offset` of the source with the weak checksum `weak`, is a copy of. The one at
+    /// the same offset is preferred, since it does not need to be written.
+    fn find(&self, weak: u32, offset: u64, window: &[u8]) -> Option<usize> {
+        let candidates = self.lookup.get(&weak)?;
+        let strong = blake3::hash(window);
+        let mut matching = candidates
+            .iter()
+            .copied()
+            .filter(|&idx| self.blocks[idx].strong == strong);
+        let first = matching.next()?;
+        let aligned = offset
+            .is_multiple_of(BLOCK_SIZE as u64)
+            .then_some((offset / BLOCK_SIZE as u64) as usize);
+        match aligned {
+            Some(idx) if first == idx || matching.any(|v| v == idx) => Some(idx),
+            _ => Some(first),
+        }
+    }
+
+    /// Whether `window`, at the block boundary `offset` of the source, is the block at the same offset.
+    fn same_at(&self, offset: u64, window: &[u8]) -> bool {
+        let idx = (offset / BLOCK_SIZE as u64) as usize;
+        self.blocks.get(idx).is_some_and(|block| {
+            window.len() == BLOCK_SIZE
+                && Rolling::new(window).digest() == block.weak
+                && blake3::hash(window) == block.strong
+        })
+    }
+}
+
+/// Sequential reads of a file that keep the bytes after the block being looked at in memory.
+struct Window<'a> {
+    file: &'a File,
+    buffer: Vec<u8>,
+    /// the offset of the first byte of `buffer`
+    start: u64,
+}
+
+impl<'a> Window<'a> {
+    fn new(mut file: &'a File) -> io::Result<Self> {
+        file.seek(SeekFrom::Start(0))?;
+        Ok(Self {
+            file,
+            buffer: Vec::new(),
+            start: 0,
+        })
+    }
+
+    /// Up to `len` bytes at `offset`, fewer at the end of the file. Offsets never go back more than a block.
+    fn get(&mut self, offset: u64, len: usize) -> io::Result<&[u8]> {
+        let keep = offset.saturating_sub(2 * BLOCK_SIZE as u64);
+        if keep > self.start + 4 * BLOCK_SIZE as u64 {
+            self.buffer.drain(..(keep - self.start) as usize);
+            self.start = keep;
+        }
+        let skip = (offset - self.start) as usize;
+        let have = self.buffer.len();
+        if have < skip + len {
+            self.buffer
+                .resize((skip + len).max(have + 16 * BLOCK_SIZE), 0);
+            let cnt = read_full(self.file, &mut self.buffer[have..])?;
+            self.buffer.truncate(have + cnt);
+        }
+        let end = (skip + len).min(self.buffer.len());
+        Ok(&self.buffer[skip.min(end)..end])
+    }
+}
+
+/// Finds where each part of `src` can be taken from.
+fn scan(src: &File, signature: &Signature) -> io::Result<Vec<Op>> {
+    let len = src.metadata()?.len();
+    let block = BLOCK_SIZE as u64;
+    let mut window = Window::new(src)?;
+    let mut ops = Vec::new();
+    // the start of the literal data before `pos`
+    let (mut pos, mut literal) = (0, 0);
+    let mut rolling: Option<Rolling> = None;
+    while pos + block <= len && !signature.blocks.is_empty() {
+        let weak = match &rolling {
+            Some(rolling) => rolling.digest(),
+            None => rolling
+                .insert(Rolling::new(window.get(pos, BLOCK_SIZE)?))
+                .digest(),
+        };
+        let found = signature.find(weak, pos, window.get(pos, BLOCK_SIZE)?);
+        if let Some(idx) = found {
+            let from = idx as u64 * block;
+            // a block moved across the next boundary would rewrite the block there, even when it did not change
+            let next = (pos / block + 1) * block;
+            if from != pos
+                && next + block <= len
+                && signature.same_at(next, window.get(next, BLOCK_SIZE)?)
+            {
+                pos = next;
+                rolling = None;
+                continue;
+            }
+            push(
+                &mut ops,
+                Op::Literal {
+                    at: literal,
+                    len: pos - literal,
+                },
+            );
+            push(
+                &mut ops,
+                match from == pos {
+                    true => Op::Same {
+                        at: pos,
+                        len: block,
+                    },
+                    false => Op::Moved {
+                        from,
+                        to: pos,
+                        len: block,
+                    },
+                },
+            );
+            pos += block;
+            literal = pos;
+            rolling = None;
+        } else if pos + block < len {
+            let bytes = window.get(pos, BLOCK_SIZE + 1)?;
+            let (out, new) = (bytes[0], bytes[BLOCK_SIZE]);
+            if let Some(rolling) = &mut rolling {
+                rolling.roll(out, new)
+            }
+            pos += 1;
+        } else {
+            break;
+        }
+    }
+
+    let rest = len - literal;
+    let tail_start = signature.blocks.len() as u64 * block;
+    let same_tail = match signature.tail {
+        Some((tail_len, strong)) if literal == tail_start && rest == tail_len => {
+            blake3::hash(window.get(literal, rest as usize)?) == strong
+        }
+        _ => false,
+    };
+    push(
+        &mut ops,
+        match same_tail {
+            true => Op::Same {
+                at: literal,
+                len: rest,
+            },
+            false => Op::Literal {
+                at: literal,
+                len: rest,
+            },
+        },
+    );
+    Ok(ops)
+}
+
+/// Writes `ops` into `dst`. Blocks moving towards the end are copied first, from the last one back, then those moving
+/// towards the start, from the first one on, so that neither kind overwrites a block of its own kind before it is
+/// copied. A block moving towards the start whose old place was already overwritten is written from `src`, as is all
+/// the literal data at the end.
+fn apply<F: FnMut(u64)>(
+    src: &File,
+    dst: &File,
+    ops: Vec<Op>,
+    progress: &mut Progress<F>,
+) -> io::Result<u64> {
+    let len = src.metadata()?.len();
+    let mut buffer = vec![0; BLOCK_SIZE];
+    let mut saved = 0;
+    let mut forward = Vec::new();
+    let mut backward = Vec::new();
+    let mut literals = Vec::new();
+    for op in ops {
+        match op {
+            Op::Same { len, .. } => {
+                saved += len;
+                progress.skip(len);
+            }
+            Op::Moved { from, to, .. } if from < to => forward.push(op),
+            Op::Moved { .. } => backward.push(op),
+            Op::Literal { at, len } => literals.push((at, len)),
+        }
+    }
+
+    // the places written by blocks moving towards the end, from the last one back
+    let mut written = Vec::new();
+    for op in forward.iter().rev() {
+        let Op::Moved { from, to, len } = *op else {
+            continue;
+        };
+        let mut end = len;
+        while end > 0 {
+            let cnt = end.min(BLOCK_SIZE as u64);
+            end -= cnt;
+            copy_within(dst, from + end, to + end, &mut buffer[..cnt as usize])?;
+            progress.advance(cnt);
+        }
+        saved += len;
+        written.push((to, to + len));
+    }
+    written.reverse();
+
+    for op in backward {
+        let Op::Moved { from, to, len } = op else {
+            continue;
+        };
+        let first_after = written.partition_point(|&(_, end)| end <= from);
+        if written
+            .get(first_after)
+            .is_some_and(|&(start, _)| start < from + len)
+        {
+            literals.push((to, len));
+            continue;
+        }
+        let mut done = 0;
+        while done < len {
+            let cnt = (len - done).min(BLOCK_SIZE as u64);
+            copy_within(dst, from + done, to + done, &mut buffer[..cnt as usize])?;
+            progress.advance(cnt);
+            done += cnt;
+        }
+        saved += len;
+    }
+
+    let (mut src, mut dst) = (src, dst);
+    for (at, len) in literals {
+        src.seek(SeekFrom::Start(at))?;
+        dst.seek(SeekFrom::Start(at))?;
+        let mut done = 0;
+        while done < len {
+            let cnt = (len - done).min(BLOCK_SIZE as u64) as usize;
+            if read_full(src, &mut buffer[..cnt])? < cnt {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "the source shrank while it was being copied",
+                ));
+            }
+            dst.write_all(&buffer[..cnt])?;
+            progress.advance(cnt as u64);
+            done += cnt as u64;
+        }
+    }
+    dst.set_len(len)?;
+    Ok(saved)
+}
+
+/// Copies the `buffer.len()` bytes at `from` of `file` to `to`.
+fn copy_within(mut file: &File, from: u64, to: u64, buffer: &mut [u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(from))?;
+    if read_full(file, buffer)? < buffer.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "the destination shrank while it was being updated",
+        ));
+    }
+    file.seek(SeekFrom::Start(to))?;
+    file.write_all(buffer)
+}
+
+/// Fills as much of `buffer` as the file allows, returning less than its length only at the end of the file.
+fn read_full(mut file: &File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(cnt) => filled += cnt,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod test_mod {
+    use super::*;
+
+    /// Bytes that do not repeat within a block, as the content of real files mostly does not.
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn update_file(old: &[u8], new: &[u8]) -> (Vec<u8>, u64, u64) {
+        let dir = tempfile::tempdir().unwrap();
+        let (src_path, dst_path) = (dir.path().join("new"), dir.path().join("old"));
+        std::fs::write(&src_path, new).unwrap();
+        std::fs::write(&dst_path, old).unwrap();
+        let src = File::open(&src_path).unwrap();
+        let dst = File::options()
+            .read(true)
+            .write(true)
+            .open(&dst_path)
+            .unwrap();
+        let mut reported = 0;
+        let mut progress = Progress::new(|cnt| reported += cnt);
+        let saved = update(&src, &dst, &mut progress).unwrap();
+        std::mem::drop(progress);
+        (std::fs::read(&dst_path).unwrap(), saved, reported)
+    }
+
+    #[test]
+    fn test_shifted_blocks() {
+        let old = noise(1 << 20, 7);
+        let block = BLOCK_SIZE;
+
+        // a byte inserted moves everything after it towards the end
+        let mut inserted = old.clone();
+        inserted.insert(100_000, 42);
+        let (res, saved, reported) = update_file(&old, &inserted);
+        assert!(res == inserted && reported == inserted.len() as u64);
+        assert_eq!(saved, (old.len() - block) as u64);
+
+        // bytes removed move everything after them towards the start, and blocks swapped both ways
+        let mut changed = old.clone();
+        changed.drain(700_000..700_003);
+        let (first, second) = changed[..2 * block].split_at_mut(block);
+        first.swap_with_slice(second);
+        let (res, saved, reported) = update_file(&old, &changed);
+        assert!(res == changed && reported == changed.len() as u64);
+        assert!(saved >= (old.len() - 2 * block) as u64);
+
+        // a shorter file with nothing in common
+        let other = noise(3 * block + 10, 11);
+        let (res, saved, _) = update_file(&old, &other);
+        assert!(res == other && saved == 0);
+        let (res, saved, _) = update_file(&other, &other);
+        assert!(res == other && saved == other.len() as u64);
+    }
+}
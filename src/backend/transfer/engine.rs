@@ -0,0 +1,401 @@
+//! The copy engine used by the transfer sessions.
+//!
+//! On Linux a file is first cloned with a reflink (`FICLONE`) which is instantaneous on
+//! copy-on-write filesystems such as btrfs and xfs. When that is not possible the data is moved
+//! inside the kernel with `copy_file_range`, falling back to `sendfile`, and finally to a plain
+//! userspace copy with a buffer sized after the file. Holes in sparse files are skipped instead
+//! of being written out as zeroes. Every other platform uses the buffered copy.
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    time::{Duration, Instant},
+};
+
+const MIN_BUFFER_SIZE: u64 = 64 * 1024;
+const MAX_BUFFER_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Upper bound on the bytes requested from the kernel in one call, so that progress keeps moving on large files.
+#[cfg(target_os = "linux")]
+const KERNEL_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+const PROGRESS_BATCH_BYTES: u64 = 1024 * 1024;
+const PROGRESS_BATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CopyStrategy {
+    /// Use the fastest method the platform and filesystem support.
+    Auto,
+    /// Always copy through a userspace buffer.
+    Buffered,
+}
+
+/// The method that ended up copying the bytes of a file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CopyMethod {
+    Reflink,
+    CopyFileRange,
+    SendFile,
+    Buffered,
+}
+
+/// Collects progress from the engine and forwards it to `sink` in batches.
+///
+/// The transfer state sits behind a mutex that is shared by every file of a session, so reporting each chunk individually
+/// costs more than the copy itself once chunks get small. Pending bytes are flushed every megabyte, every 100ms, and on drop.
+pub struct Progress<F: FnMut(u64)> {
+    pending: u64,
+    last_flush: Instant,
+    sink: F,
+    pacer: Option<Box<dyn Pacer>>,
+}
+
+/// Holds a copy back to some bandwidth limit.
+pub trait Pacer {
+    /// The most bytes to move at once, or `None` while the copy is not limited. It is asked again before every chunk.
+    fn max_chunk(&self) -> Option<u64>;
+    /// Called after `bytes` were actually moved. Blocks for as long as the copy must wait.
+    fn pace(&self, bytes: u64);
+}
+
+impl<F: FnMut(u64)> Progress<F> {
+    pub fn new(sink: F) -> Self {
+        Self {
+            pending: 0,
+            last_flush: Instant::now(),
+            sink,
+            pacer: None,
+        }
+    }
+
+    /// Like `new`, but moves data in chunks no larger than `pacer` allows and lets it pause the copy after each one.
+    pub fn paced(sink: F, pacer: impl Pacer + 'static) -> Self {
+        let mut progress = Self::new(sink);
+        progress.pacer = Some(Box::new(pacer));
+        progress
+    }
+
+    /// The number of bytes to move next, out of the `preferred` amount.
+    fn chunk(&self, preferred: u64) -> u64 {
+        match self.pacer.as_ref().and_then(|p| p.max_chunk()) {
+            Some(max) => preferred.min(max),
+            None => preferred,
+        }
+    }
+
+    /// Records `bytes` that were read and written.
+    pub fn advance(&mut self, bytes: u64) {
+        self.skip(bytes);
+        if let Some(pacer) = &self.pacer {
+            pacer.pace(bytes);
+        }
+    }
+
+    /// Records `bytes` that count as transferred without having been moved, like holes and cloned extents.
+    pub fn skip(&mut self, bytes: u64) {
+        self.pending += bytes;
+        if self.pending >= PROGRESS_BATCH_BYTES
+            || self.last_flush.elapsed() >= PROGRESS_BATCH_INTERVAL
+            || self.pacer.is_some()
+        {
+            self.flush();
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if self.pending > 0 {
+            (self.sink)(self.pending);
+            self.pending = 0;
+        }
+        self.last_flush = Instant::now();
+    }
+}
+
+impl<F: FnMut(u64)> Drop for Progress<F> {
+    fn drop(&mut self) {
+        self.flush()
+    }
+}
+
+/// Copies the entire content of `src` into `dst`, which is expected to be a newly created, empty file.
+pub fn copy_contents<F: FnMut(u64)>(
+    src: &File,
+    dst: &File,
+    strategy: CopyStrategy,
+    progress: &mut Progress<F>,
+) -> io::Result<CopyMethod> {
+    let len = src.metadata()?.len();
+    #[cfg(target_os = "linux")]
+    if strategy == CopyStrategy::Auto && len > 0 {
+        return linux::copy(src, dst, len, progress);
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = strategy;
+    buffered_range(src, dst, 0, len, progress)?;
+    Ok(CopyMethod::Buffered)
+}
+
+/// Size of the buffer used to copy a file of `len` bytes: large files get large buffers, small files do not pay for them.
+fn buffer_size(len: u64) -> usize {
+    (len / 16)
+        .next_power_of_two()
+        .clamp(MIN_BUFFER_SIZE, MAX_BUFFER_SIZE) as usize
+}
+
+fn buffered_range<F: FnMut(u64)>(
+    mut src: &File,
+    mut dst: &File,
+    offset: u64,
+    len: u64,
+    progress: &mut Progress<F>,
+) -> io::Result<()> {
+    src.seek(SeekFrom::Start(offset))?;
+    dst.seek(SeekFrom::Start(offset))?;
+    let mut buffer = vec![0; buffer_size(len)];
+    let mut remaining = len;
+    while remaining > 0 {
+        let want = progress.chunk(remaining.min(buffer.len() as u64)) as usize;
+        let cnt = match src.read(&mut buffer[..want]) {
+            Ok(0) => return Err(shrank()),
+            Ok(cnt) => cnt,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        dst.write_all(&buffer[..cnt])?;
+        remaining -= cnt as u64;
+        progress.advance(cnt as u64);
+    }
+    Ok(())
+}
+
+/// The error for a source that ended before the length it had when the copy started, rather than padding its copy.
+fn shrank() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "the source shrank while it was being copied",
+    )
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{CopyMethod, Progress, KERNEL_CHUNK_SIZE};
+    use std::{
+        fs::File,
+        io::{self, Seek, SeekFrom},
+        os::unix::{fs::MetadataExt, io::AsRawFd},
+    };
+
+    pub(super) fn copy<F: FnMut(u64)>(
+        src: &File,
+        dst: &File,
+        len: u64,
+        progress: &mut Progress<F>,
+    ) -> io::Result<CopyMethod> {
+        if reflink(src, dst).is_ok() {
+            progress.skip(len);
+            return Ok(CopyMethod::Reflink);
+        }
+
+        let ranges = if is_sparse(src)? {
+            data_ranges(src, len)
+        } else {
+            vec![(0, len)]
+        };
+
+        let mut method = CopyMethod::CopyFileRange;
+        let mut copied_upto = 0;
+        for (start, end) in ranges {
+            // holes are never read, but they still count as transferred
+            progress.skip(start - copied_upto);
+            method = copy_range(src, dst, start, end - start, method, progress)?;
+            copied_upto = end;
+        }
+        progress.skip(len - copied_upto);
+        // a hole at the end of the file is only materialised by the length
+        dst.set_len(len)?;
+        Ok(method)
+    }
+
+    fn reflink(src: &File, dst: &File) -> io::Result<()> {
+        let res = unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) };
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    fn is_sparse(src: &File) -> io::Result<bool> {
+        let metadata = src.metadata()?;
+        Ok(metadata.blocks() * 512 < metadata.len())
+    }
+
+    /// Returns the `[start, end)` regions of `src` that hold data. Filesystems without `SEEK_DATA` report a single region.
+    fn data_ranges(src: &File, len: u64) -> Vec<(u64, u64)> {
+        let fd = src.as_raw_fd();
+        let mut ranges = Vec::new();
+        let mut pos = 0;
+        while pos < len {
+            let start = unsafe { libc::lseek(fd, pos as libc::off_t, libc::SEEK_DATA) };
+            if start < 0 {
+                if io::Error::last_os_error().raw_os_error() == Some(libc::ENXIO) {
+                    break;
+                }
+                return vec![(0, len)];
+            }
+            let end = unsafe { libc::lseek(fd, start, libc::SEEK_HOLE) };
+            if end < 0 {
+                return vec![(0, len)];
+            }
+            let end = (end as u64).min(len);
+            ranges.push((start as u64, end));
+            pos = end;
+        }
+        ranges
+    }
+
+    /// Copies one region with `method`, moving down to the next method whenever the kernel refuses the current one.
+    fn copy_range<F: FnMut(u64)>(
+        src: &File,
+        dst: &File,
+        mut offset: u64,
+        mut remaining: u64,
+        mut method: CopyMethod,
+        progress: &mut Progress<F>,
+    ) -> io::Result<CopyMethod> {
+        while remaining > 0 {
+            let chunk = progress.chunk(remaining.min(KERNEL_CHUNK_SIZE)) as usize;
+            let res = match method {
+                CopyMethod::CopyFileRange => copy_file_range(src, dst, offset, chunk),
+                CopyMethod::SendFile => sendfile(src, dst, offset, chunk),
+                CopyMethod::Reflink | CopyMethod::Buffered => {
+                    super::buffered_range(src, dst, offset, remaining, progress)?;
+                    return Ok(CopyMethod::Buffered);
+                }
+            };
+            match res {
+                Ok(0) if offset >= src.metadata()?.len() => return Err(super::shrank()),
+                // procfs, sysfs and some FUSE filesystems have nothing to give the kernel before the end of the file
+                Ok(0) => method = CopyMethod::Buffered,
+                Ok(cnt) => {
+                    offset += cnt as u64;
+                    remaining -= cnt as u64;
+                    progress.advance(cnt as u64);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) if is_unsupported(&e) => {
+                    method = match method {
+                        CopyMethod::CopyFileRange => CopyMethod::SendFile,
+                        _ => CopyMethod::Buffered,
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(method)
+    }
+
+    fn is_unsupported(e: &io::Error) -> bool {
+        matches!(
+            e.raw_os_error(),
+            Some(libc::ENOSYS | libc::EXDEV | libc::EOPNOTSUPP | libc::EINVAL | libc::EPERM)
+        )
+    }
+
+    fn copy_file_range(src: &File, dst: &File, offset: u64, len: usize) -> io::Result<usize> {
+        let mut off_in = offset as libc::loff_t;
+        let mut off_out = offset as libc::loff_t;
+        let res = unsafe {
+            libc::copy_file_range(
+                src.as_raw_fd(),
+                &mut off_in,
+                dst.as_raw_fd(),
+                &mut off_out,
+                len,
+                0,
+            )
+        };
+        if res < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(res as usize)
+        }
+    }
+
+    fn sendfile(src: &File, mut dst: &File, offset: u64, len: usize) -> io::Result<usize> {
+        dst.seek(SeekFrom::Start(offset))?;
+        let mut off_in = offset as libc::off_t;
+        let res = unsafe { libc::sendfile(dst.as_raw_fd(), src.as_raw_fd(), &mut off_in, len) };
+        if res < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(res as usize)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_mod {
+    use super::*;
+
+    #[test]
+    fn test_sparse_copy() {
+        // Run: cargo test --features backend --lib -- backend::transfer::engine::test_mod
+        let dir = tempfile::tempdir().unwrap();
+        let src_path = dir.path().join("sparse");
+        let dst_path = dir.path().join("copy");
+
+        let mut src = File::create(&src_path).unwrap();
+        src.seek(SeekFrom::Start(8 << 20)).unwrap();
+        src.write_all(b"middle").unwrap();
+        src.set_len(32 << 20).unwrap();
+        std::mem::drop(src);
+
+        for strategy in [CopyStrategy::Auto, CopyStrategy::Buffered] {
+            let src = File::open(&src_path).unwrap();
+            let dst = File::create(&dst_path).unwrap();
+            let mut reported = 0;
+            let mut progress = Progress::new(|cnt| reported += cnt);
+            copy_contents(&src, &dst, strategy, &mut progress).unwrap();
+            std::mem::drop(progress);
+            assert_eq!(reported, 32 << 20);
+            assert_eq!(
+                std::fs::read(&src_path).unwrap(),
+                std::fs::read(&dst_path).unwrap()
+            );
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let src = File::open(&src_path).unwrap();
+            let dst = File::create(&dst_path).unwrap();
+            copy_contents(&src, &dst, CopyStrategy::Auto, &mut Progress::new(|_| ())).unwrap();
+            let (src_meta, dst_meta) = (src.metadata().unwrap(), dst.metadata().unwrap());
+            if src_meta.blocks() * 512 < src_meta.len() {
+                assert!(dst_meta.blocks() * 512 < dst_meta.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_source_shorter_than_expected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src_path, dst_path) = (dir.path().join("short"), dir.path().join("copy"));
+        std::fs::write(&src_path, vec![7; 100]).unwrap();
+
+        let src = File::open(&src_path).unwrap();
+        let dst = File::create(&dst_path).unwrap();
+        let err = buffered_range(&src, &dst, 0, 200, &mut Progress::new(|_| ())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        #[cfg(target_os = "linux")]
+        {
+            let dst = File::create(&dst_path).unwrap();
+            let err = linux::copy(&src, &dst, 200, &mut Progress::new(|_| ())).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+            // nothing past what was read is made up
+            assert!(dst.metadata().unwrap().len() <= 100);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Classification of the items met while walking a transfer, according to its link and special file policies.
+
+use std::{fs::Metadata, io, path::Path};
+
+use crate::msg::{LinkPolicy, SpecialFilePolicy, TransferOptions};
+
+/// Identifies a folder independently of the path used to reach it, so that links looping back into a tree are noticed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) struct FileId {
+    dev: u64,
+    ino: u64,
+}
+
+impl FileId {
+    #[cfg(unix)]
+    fn of(_path: &Path, metadata: &Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn of(path: &Path, _metadata: &Metadata) -> Self {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        std::fs::canonicalize(path)
+            .unwrap_or_else(|_| path.to_path_buf())
+            .hash(&mut hasher);
+        Self {
+            dev: 0,
+            ino: hasher.finish(),
+        }
+    }
+}
+
+pub(super) enum Entry {
+    Dir(FileId),
+    /// a regular file and its length
+    File(u64),
+    /// a link to recreate as a link, and the length of its target path
+    Link(u64),
+    Special(Metadata),
+    Skip,
+}
+
+pub(super) fn inspect(path: &Path, options: &TransferOptions) -> io::Result<Entry> {
+    let mut metadata = std::fs::symlink_metadata(path)?;
+    if metadata.file_type().is_symlink() {
+        match options.links {
+            LinkPolicy::Skip => return Ok(Entry::Skip),
+            LinkPolicy::CopyAsLink => return Ok(Entry::Link(metadata.len())),
+            LinkPolicy::Follow => match std::fs::metadata(path) {
+                Ok(target) => metadata = target,
+                // a dangling link has nothing to follow, so keep the link
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Ok(Entry::Link(metadata.len()))
+                }
+                Err(e) => return Err(e),
+            },
+        }
+    }
+
+    if metadata.is_dir() {
+        Ok(Entry::Dir(FileId::of(path, &metadata)))
+    } else if metadata.is_file() {
+        Ok(Entry::File(metadata.len()))
+    } else if is_special(&metadata) && options.special_files == SpecialFilePolicy::Recreate {
+        Ok(Entry::Special(metadata))
+    } else {
+        Ok(Entry::Skip)
+    }
+}
+
+#[cfg(unix)]
+fn is_special(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    let kind = metadata.file_type();
+    kind.is_fifo() || kind.is_block_device() || kind.is_char_device()
+}
+
+#[cfg(not(unix))]
+fn is_special(_metadata: &Metadata) -> bool {
+    false
+}
+
+/// Creates a FIFO or device node at `dst` like the one described by `metadata`.
+#[cfg(unix)]
+pub(super) fn recreate_special(metadata: &Metadata, dst: &Path) -> io::Result<()> {
+    use std::os::unix::{ffi::OsStrExt, fs::MetadataExt};
+    let path = std::ffi::CString::new(dst.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let res = unsafe {
+        libc::mknod(
+            path.as_ptr(),
+            metadata.mode() as libc::mode_t,
+            metadata.rdev() as libc::dev_t,
+        )
+    };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+pub(super) fn recreate_special(_metadata: &Metadata, _dst: &Path) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,1017 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    thread,
+    time::Instant,
+};
+
+use crate::{
+    collections::Registry,
+    msg::{
+        FinishedTransfer, TransferError, TransferMsg, TransferOptions, TransferOutcome,
+        TransferPlan, TransferSessionID, TransferSize, TransferState,
+    },
+    PitouFile, PitouFilePath,
+};
+
+use super::clipboard;
+use engine::{CopyStrategy, Pacer, Progress};
+use links::{Entry, FileId};
+use throttle::Bandwidth;
+use tracker::ProgressTracker;
+
+pub mod archive;
+mod blocks;
+pub mod engine;
+mod links;
+mod plan;
+mod preserve;
+mod throttle;
+mod tracker;
+pub(crate) mod verify;
+
+impl TransferState {
+    /// Adds the supplied value to the current size. This method automatically checks if the transfer is completed changes the state from Active to Terminated
+    ///
+    /// A hypothetical problem with this approach it that if a number of folders and some number of files are being copied, and the files somehow finish getting copied before the folder,
+    /// this operation will change the state of the transfer to Terminated.
+    ///
+    /// A simple fix is to pretend the folders also have size. This will be used in both the total and current computations.
+    fn append_current(&mut self, val: u64) {
+        if let Self::Active(TransferSize { total: _, current }) = self {
+            *current += val;
+            /*
+            if *current == *total {
+                *self = TransferState::Terminated(TransferSize { total: *total, current: *current })
+            }
+            */
+        }
+    }
+
+    fn append_total(&mut self, val: u64) {
+        if let Self::Initializing(total) = self {
+            *total += val
+        }
+    }
+
+    fn end_init(&mut self) {
+        if let Self::Initializing(total) = *self {
+            let current = 0;
+            *self = Self::Active(TransferSize { total, current })
+        }
+    }
+}
+
+struct TransferConfig {
+    id: TransferSessionID,
+    state: Mutex<TransferState>,
+    started: Mutex<Instant>,
+    copy: bool,
+    options: TransferOptions,
+    mismatches: Mutex<Vec<PitouFilePath>>,
+    progress: Mutex<ProgressTracker>,
+    bandwidth: Bandwidth,
+    errors: Mutex<Vec<TransferError>>,
+    /// the items being transferred and the folder they go to
+    paths: Mutex<Vec<PathBuf>>,
+}
+
+impl TransferConfig {
+    fn is_ongoing(&self) -> bool {
+        !matches!(*self.state.lock().unwrap(), TransferState::Terminated(_))
+    }
+
+    fn start_now(&self) {
+        *self.started.lock().unwrap() = Instant::now();
+        self.progress.lock().unwrap().record(0);
+    }
+
+    /// Adds `val` transferred bytes to the session and feeds them to the throughput estimates.
+    fn advance(&self, val: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.append_current(val);
+        if let TransferState::Active(TransferSize { total: _, current }) = *state {
+            std::mem::drop(state);
+            self.progress.lock().unwrap().record(current);
+        }
+    }
+
+    fn record_error(&self, path: &Path, message: String) {
+        let path = PitouFilePath::from_pathbuf(path.to_path_buf());
+        self.errors
+            .lock()
+            .unwrap()
+            .push(TransferError { path, message });
+    }
+
+    /// Ends the session and files its summary in the history. `failed` of the `total` top-level items could not be
+    /// transferred at all.
+    fn terminate_now(&self, failed: usize, total: usize) {
+        let mut state = self.state.lock().unwrap();
+        let TransferState::Active(sz) = *state else {
+            return;
+        };
+        *state = TransferState::Terminated(sz);
+        std::mem::drop(state);
+
+        let mut errors = self.errors.lock().unwrap().clone();
+        errors.extend(
+            self.mismatches
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|v| TransferError {
+                    path: PitouFilePath::from_pathbuf(v.path.clone()),
+                    message: String::from("the copy did not match its source and was discarded"),
+                }),
+        );
+        let outcome = TransferOutcome::judge(errors.len(), failed, total);
+        let finished = FinishedTransfer {
+            id: self.id,
+            copy: self.copy,
+            outcome,
+            duration: self.started.lock().unwrap().elapsed(),
+            bytes: sz.current,
+            files: self.progress.lock().unwrap().files_done(),
+            errors,
+        };
+        let mut history = get_history().lock().unwrap();
+        if history.len() == HISTORY_LIMIT {
+            history.pop_back();
+        }
+        history.push_front(finished);
+    }
+
+    fn begin_transfer(self: &Arc<Self>, items: Arc<Vec<PitouFile>>, dst: PitouFilePath) {
+        let items = items
+            .iter()
+            .map(|v| (v.path.path.clone(), dst.path.clone()))
+            .collect();
+        self.begin_transfer_each(items)
+    }
+
+    /// Transfers each item of `items` into the folder paired with it.
+    fn begin_transfer_each(self: &Arc<Self>, items: Vec<(PathBuf, PathBuf)>) {
+        let mut paths = self.paths.lock().unwrap();
+        for (src, dst) in &items {
+            paths.push(src.clone());
+            if !paths.contains(dst) {
+                paths.push(dst.clone());
+            }
+        }
+        std::mem::drop(paths);
+        let config = self.clone();
+        let copy = self.copy;
+        let _ = thread::spawn(move || {
+            if copy {
+                AllItemsCopySesssion::init(config, items)
+            } else {
+                AllItemsCopySesssion::init(config, items)
+            }
+        });
+    }
+
+    fn read(&self) -> TransferMsg {
+        let state = *self.state.lock().unwrap();
+        let id = self.id;
+        let time_elapsed = self.started.lock().unwrap().elapsed();
+        let mismatches = self
+            .mismatches
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|v| PitouFilePath::from_pathbuf(v.path.clone()))
+            .collect();
+        let size = match state {
+            TransferState::Initializing(_) => None,
+            TransferState::Active(size) | TransferState::Terminated(size) => Some(size),
+        };
+        let progress = self.progress.lock().unwrap().read(size, time_elapsed);
+        let bandwidth_limit = throttle::effective_limit(&self.bandwidth);
+
+        if self.copy {
+            TransferMsg::Copy {
+                id,
+                state,
+                time_elapsed,
+                progress,
+                mismatches,
+                bandwidth_limit,
+            }
+        } else {
+            TransferMsg::Move {
+                id,
+                state,
+                time_elapsed,
+                progress,
+                mismatches,
+                bandwidth_limit,
+            }
+        }
+    }
+}
+
+const HYPOTHETICAL_FOLDER_SIZE: u64 = 1;
+/// Number of finished sessions remembered by the history.
+const HISTORY_LIMIT: usize = 64;
+type CONFIGURATIONS = Mutex<Registry<Arc<TransferConfig>>>;
+static SESSIONS: OnceLock<CONFIGURATIONS> = OnceLock::new();
+static HISTORY: OnceLock<Mutex<VecDeque<FinishedTransfer>>> = OnceLock::new();
+
+fn get_sessions() -> &'static CONFIGURATIONS {
+    SESSIONS.get_or_init(|| Mutex::new(Registry::new()))
+}
+
+fn get_history() -> &'static Mutex<VecDeque<FinishedTransfer>> {
+    HISTORY.get_or_init(|| Mutex::new(VecDeque::with_capacity(HISTORY_LIMIT)))
+}
+
+fn add_new_session(copy: bool, options: TransferOptions) -> Arc<TransferConfig> {
+    let mut sessions = get_sessions().lock().unwrap();
+    let key = sessions.insert_with(|key| {
+        Arc::new(TransferConfig {
+            id: key.into(),
+            state: Mutex::new(TransferState::Initializing(0)),
+            started: Mutex::new(Instant::now()),
+            copy,
+            options,
+            mismatches: Mutex::new(Vec::new()),
+            progress: Mutex::new(ProgressTracker::new()),
+            bandwidth: Bandwidth::new(options.bandwidth_limit),
+            errors: Mutex::new(Vec::new()),
+            paths: Mutex::new(Vec::new()),
+        })
+    });
+    sessions.get(key).unwrap().clone()
+}
+
+fn dst_temp(src: &Path, dst: &Path) -> PathBuf {
+    let name = src.file_name().unwrap();
+    dst.join(format!(".{}", name.to_str().unwrap()))
+}
+
+fn dst_real(src: &Path, dst: &Path) -> PathBuf {
+    let name = src.file_name().unwrap();
+    dst.join(name)
+}
+
+pub fn get_all_active_sessions() -> Vec<TransferMsg> {
+    get_sessions()
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(_, v)| if v.is_ongoing() { Some(v.read()) } else { None })
+        .collect()
+}
+
+fn find_session(id: TransferSessionID) -> Option<Arc<TransferConfig>> {
+    get_sessions().lock().unwrap().get(id.into()).cloned()
+}
+
+/// Limits the session with `id` to `limit` bytes per second, or lifts its limit. Takes effect on the running copy.
+///
+/// Returns false if there is no such session.
+pub fn set_session_bandwidth_limit(id: TransferSessionID, limit: Option<u64>) -> bool {
+    match find_session(id) {
+        Some(config) => {
+            config.bandwidth.set_limit(limit);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Limits the combined throughput of all transfer sessions to `limit` bytes per second, or lifts that limit.
+pub fn set_global_bandwidth_limit(limit: Option<u64>) {
+    throttle::set_global_limit(limit)
+}
+
+pub fn get_global_bandwidth_limit() -> Option<u64> {
+    throttle::global_limit()
+}
+
+pub fn get_session_with_id(id: TransferSessionID) -> Option<TransferMsg> {
+    find_session(id).map(|v| v.read())
+}
+
+/// Ongoing sessions reading or writing anything under `path`.
+pub(crate) fn sessions_within(path: &Path) -> Vec<TransferSessionID> {
+    get_sessions()
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, v)| {
+            v.is_ongoing() && v.paths.lock().unwrap().iter().any(|u| u.starts_with(path))
+        })
+        .map(|(_, v)| v.id)
+        .collect()
+}
+
+/// Forgets terminated sessions. Their summaries remain available from [`get_finished_sessions`].
+pub fn clean_dead_sessions() {
+    get_sessions().lock().unwrap().retain(|_, v| v.is_ongoing());
+}
+
+/// Summaries of the most recently finished sessions, newest first.
+pub fn get_finished_sessions() -> Vec<FinishedTransfer> {
+    get_history().lock().unwrap().iter().cloned().collect()
+}
+
+pub fn get_finished_session(id: TransferSessionID) -> Option<FinishedTransfer> {
+    get_history()
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|v| v.id == id)
+        .cloned()
+}
+
+pub fn clear_finished_sessions() {
+    get_history().lock().unwrap().clear()
+}
+
+/// Copies `items` into `dst` without going through the clipboard, as tools acting on many files at once do. The
+/// originals are left in place.
+pub fn copy_items(
+    items: Vec<PitouFile>,
+    dst: PitouFilePath,
+    options: TransferOptions,
+) -> TransferSessionID {
+    let config = add_new_session(true, options);
+    config.begin_transfer(Arc::new(items), dst);
+    config.id
+}
+
+/// Copies each item of `items` into the folder paired with it, all in one session. Files already at the destination
+/// are replaced.
+pub(crate) fn copy_each(
+    items: Vec<(PathBuf, PathBuf)>,
+    options: TransferOptions,
+) -> TransferSessionID {
+    let config = add_new_session(true, options);
+    config.begin_transfer_each(items);
+    config.id
+}
+
+pub async fn paste_items(dst: PitouFilePath) -> Option<TransferSessionID> {
+    paste_items_with_options(dst, TransferOptions::default()).await
+}
+
+pub async fn paste_items_with_options(
+    dst: PitouFilePath,
+    options: TransferOptions,
+) -> Option<TransferSessionID> {
+    match clipboard::paste().await {
+        None => None,
+        Some(v) => match v {
+            clipboard::ClipboardItem::Copied(items) => {
+                let config = add_new_session(true, options);
+                config.begin_transfer(items, dst);
+                Some(config.id)
+            }
+            clipboard::ClipboardItem::Cut(items) => {
+                let config = add_new_session(false, options);
+                config.begin_transfer(items, dst);
+                Some(config.id)
+            }
+        },
+    }
+}
+
+/// Describes what pasting the clipboard into `dst` would involve, without starting a transfer or consuming the clipboard.
+pub async fn plan_paste(dst: PitouFilePath) -> Option<TransferPlan> {
+    plan_paste_with_options(dst, TransferOptions::default()).await
+}
+
+pub async fn plan_paste_with_options(
+    dst: PitouFilePath,
+    options: TransferOptions,
+) -> Option<TransferPlan> {
+    let (items, copy) = match clipboard::peek().await? {
+        clipboard::ClipboardItem::Copied(items) => (items, true),
+        clipboard::ClipboardItem::Cut(items) => (items, false),
+    };
+    tokio::task::spawn_blocking(move || plan::plan(&items, &dst.path, copy, &options))
+        .await
+        .ok()
+}
+
+#[cfg(test)]
+mod test_mod {
+    use crate::{backend::testing, PitouFileSize};
+
+    use super::*;
+    use tokio_stream::{wrappers::IntervalStream, StreamExt};
+    #[test]
+    fn test_copy() {
+        // Run: cargo test -p pitou-core --lib -- backend::transfer::test_mod::test_copy --nocapture
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let src_path = PathBuf::from("C:\\Users\\nisaacdz\\Desktop\\Tmp\\Tmp2\\DCIT 103");
+            let dst_path = PathBuf::from("C:\\Users\\nisaacdz\\Desktop\\Tmp\\Tmp2\\PasteHere");
+
+            let items = vec![PitouFile::without_metadata(PitouFilePath::from_pathbuf(src_path))];
+            super::super::copy(items).await;
+            if let Some(session_id) = super::paste_items(PitouFilePath::from_pathbuf(dst_path)).await {
+                let mut interval = IntervalStream::new(tokio::time::interval(std::time::Duration::from_millis(500)));
+                while let Some(_) = interval.next().await {
+                    let msg = get_session_with_id(session_id).unwrap();
+                    let progress = msg.progress().clone();
+                    let (state, duration) = msg.details();
+                        match state {
+                            TransferState::Initializing(c) => println!{"Computing size: {c}"},
+                            TransferState::Active(TransferSize { total, current }) => {
+                                let elapsed = duration.as_secs_f64();
+                                let size_msg = format!{"completed {} of {}", PitouFileSize::new(current).format(), PitouFileSize::new(total).format()};
+                                let files_msg = format!{"{} of {} files ({})", progress.files_done, progress.files_total, progress.current_file};
+                                let rate_msg = format!{"{}/s", PitouFileSize::new(progress.current_rate).format()};
+                                let estimated_time_rem = progress.eta.map(|v| v.as_secs_f64()).unwrap_or(f64::NAN);
+
+                                println!("{size_msg} | {files_msg} | {rate_msg} | time-spent: {:.2}s | estimated-time-rem: {:.2}s", elapsed, estimated_time_rem)
+                            },
+                            TransferState::Terminated(TransferSize { total, current }) => {
+                                let elapsed = duration.as_secs_f64();
+                                println!{"{} of {} | total-time-spent: {:.2}s", PitouFileSize::new(current).format(), PitouFileSize::new(total).format(), elapsed};
+                                break;
+                            }
+                        }
+                }
+            } else {
+                println!("nothing on clipboard to copypaste");
+            }
+        });
+    }
+
+    fn wait_for(id: TransferSessionID) -> TransferMsg {
+        testing::wait_for(
+            || get_session_with_id(id).unwrap(),
+            TransferMsg::is_terminated,
+        )
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_preserving_metadata() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let (src, dst) = (root.join("src"), root.join("dst"));
+        std::fs::create_dir_all(src.join("inner")).unwrap();
+        std::fs::create_dir_all(&dst).unwrap();
+        std::fs::write(src.join("inner").join("photo.jpg"), b"jpeg").unwrap();
+        std::os::unix::fs::symlink("inner/photo.jpg", src.join("link")).unwrap();
+        let mtime = filetime::FileTime::from_unix_time(1_500_000_000, 0);
+        let photo = src.join("inner").join("photo.jpg");
+        filetime::set_file_mtime(&photo, mtime).unwrap();
+        std::fs::set_permissions(&photo, std::fs::Permissions::from_mode(0o640)).unwrap();
+        filetime::set_file_mtime(src.join("inner"), mtime).unwrap();
+
+        let options = TransferOptions {
+            preserve: crate::msg::PreserveMetadata::all(),
+            links: crate::msg::LinkPolicy::CopyAsLink,
+            ..Default::default()
+        };
+        let config = add_new_session(true, options);
+        let items = vec![PitouFile::without_metadata(PitouFilePath::from_pathbuf(
+            src.clone(),
+        ))];
+        config.begin_transfer(Arc::new(items), PitouFilePath::from_pathbuf(dst.clone()));
+        wait_for(config.id);
+
+        let copied = dst.join("src");
+        let photo = std::fs::metadata(copied.join("inner").join("photo.jpg")).unwrap();
+        assert_eq!(
+            filetime::FileTime::from_last_modification_time(&photo),
+            mtime
+        );
+        assert_eq!(photo.permissions().mode() & 0o777, 0o640);
+        let inner = std::fs::metadata(copied.join("inner")).unwrap();
+        assert_eq!(
+            filetime::FileTime::from_last_modification_time(&inner),
+            mtime
+        );
+        assert_eq!(
+            std::fs::read_link(copied.join("link")).unwrap(),
+            PathBuf::from("inner/photo.jpg")
+        );
+    }
+
+    #[test]
+    fn test_copy_verified() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let (src, dst) = (root.join("release.bin"), root.join("dst"));
+        std::fs::create_dir_all(&dst).unwrap();
+        std::fs::write(&src, vec![7; 3 << 20]).unwrap();
+
+        let options = TransferOptions {
+            verify: true,
+            ..Default::default()
+        };
+        let config = add_new_session(true, options);
+        let items = vec![PitouFile::without_metadata(PitouFilePath::from_pathbuf(
+            src.clone(),
+        ))];
+        config.begin_transfer(Arc::new(items), PitouFilePath::from_pathbuf(dst.clone()));
+        let msg = wait_for(config.id);
+        assert!(msg.mismatches().is_empty());
+        assert_eq!(
+            (msg.progress().files_done, msg.progress().files_total),
+            (1, 1)
+        );
+        assert_eq!(
+            std::fs::read(dst.join("release.bin")).unwrap(),
+            vec![7; 3 << 20]
+        );
+
+        let corrupt = root.join("corrupt.bin");
+        std::fs::write(&corrupt, vec![8; 3 << 20]).unwrap();
+        let corrupt_file = File::open(&corrupt).unwrap();
+        assert!(!verify::files_match(&src, &corrupt_file, &corrupt).unwrap());
+    }
+
+    #[test]
+    fn test_plan_paste() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let (src, dst) = (root.join("project"), root.join("dst"));
+        std::fs::create_dir_all(src.join("assets")).unwrap();
+        std::fs::create_dir_all(dst.join("project")).unwrap();
+        std::fs::write(src.join("main.rs"), [0; 100]).unwrap();
+        std::fs::write(src.join("assets").join("logo.png"), [0; 50]).unwrap();
+
+        let items = vec![PitouFile::without_metadata(PitouFilePath::from_pathbuf(
+            src.clone(),
+        ))];
+        let options = TransferOptions::default();
+        let plan = plan::plan(&items, &dst, true, &options);
+        assert_eq!((plan.files, plan.dirs, plan.total_bytes), (2, 2, 150));
+        assert_eq!(plan.required_space, 150);
+        assert!(plan.same_device);
+        assert!(plan.conflicts == vec![PitouFilePath::from_pathbuf(dst.join("project"))]);
+
+        // a cut is copied too, within one drive as well
+        let moved = plan::plan(&items, &dst, false, &options);
+        assert_eq!(moved.required_space, 150);
+    }
+
+    #[test]
+    fn test_finished_session_history() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let dst = root.join("dst");
+        std::fs::create_dir_all(&dst).unwrap();
+        std::fs::write(root.join("report.txt"), b"report").unwrap();
+
+        let items = vec![
+            PitouFile::without_metadata(PitouFilePath::from_pathbuf(root.join("report.txt"))),
+            PitouFile::without_metadata(PitouFilePath::from_pathbuf(root.join("missing.txt"))),
+        ];
+        let config = add_new_session(true, TransferOptions::default());
+        config.begin_transfer(Arc::new(items), PitouFilePath::from_pathbuf(dst.clone()));
+        wait_for(config.id);
+
+        let finished = get_finished_session(config.id).unwrap();
+        assert_eq!(finished.outcome, TransferOutcome::CompletedWithErrors);
+        assert_eq!((finished.files, finished.bytes), (1, 6));
+        assert_eq!(finished.errors.len(), 1);
+        assert!(finished.errors[0].path == PitouFilePath::from_pathbuf(root.join("missing.txt")));
+        assert!(dst.join("report.txt").is_file());
+
+        let stale = TransferSessionID {
+            idx: config.id.idx,
+            parity: config.id.parity + 1,
+        };
+        assert!(get_session_with_id(stale).is_none());
+    }
+
+    #[test]
+    fn test_copy_changed_blocks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let (src, dst) = (root.join("disk.qcow2"), root.join("dst"));
+        std::fs::create_dir_all(&dst).unwrap();
+        let old: Vec<u8> = (0..1 << 20).map(|v| (v % 251) as u8).collect();
+        std::fs::write(dst.join("disk.qcow2"), &old).unwrap();
+        let mut new = old.clone();
+        new[300_000..300_010].fill(0);
+        new.extend_from_slice(&[1; 1000]);
+        std::fs::write(&src, &new).unwrap();
+
+        let options = TransferOptions {
+            changed_blocks_only: true,
+            ..Default::default()
+        };
+        let config = add_new_session(true, options);
+        let items = vec![PitouFile::without_metadata(PitouFilePath::from_pathbuf(
+            src.clone(),
+        ))];
+        config.begin_transfer(Arc::new(items), PitouFilePath::from_pathbuf(dst.clone()));
+        let msg = wait_for(config.id);
+        // one block holds the edit and the appended tail is new, everything else was left alone
+        assert_eq!(msg.progress().saved_bytes, (1 << 20) - 64 * 1024);
+        assert_eq!(std::fs::read(dst.join("disk.qcow2")).unwrap(), new);
+    }
+
+    #[test]
+    fn test_copy_changed_blocks_mismatch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let (src, dst) = (root.join("dataset.bin"), root.join("dst"));
+        std::fs::create_dir_all(&dst).unwrap();
+        std::fs::write(dst.join("dataset.bin"), vec![1; 1 << 20]).unwrap();
+        std::fs::write(&src, vec![2; 1 << 20]).unwrap();
+
+        let options = TransferOptions {
+            changed_blocks_only: true,
+            verify: true,
+            bandwidth_limit: Some(64 * 1024),
+            ..Default::default()
+        };
+        let config = add_new_session(true, options);
+        let items = vec![PitouFile::without_metadata(PitouFilePath::from_pathbuf(
+            src.clone(),
+        ))];
+        config.begin_transfer(Arc::new(items), PitouFilePath::from_pathbuf(dst.clone()));
+        // the source changes under the update once its first block is written, so the result matches neither version
+        testing::wait_for(
+            || get_session_with_id(config.id).unwrap().details().0,
+            |state| matches!(state, TransferState::Active(size) if size.current > 0),
+        );
+        let mut file = std::fs::OpenOptions::new().write(true).open(&src).unwrap();
+        std::io::Write::write_all(&mut file, &vec![3; 1 << 20]).unwrap();
+        std::mem::drop(file);
+        assert!(set_session_bandwidth_limit(config.id, None));
+
+        let msg = wait_for(config.id);
+        assert!(msg.mismatches() == [PitouFilePath::from_pathbuf(src.clone())]);
+        assert_eq!(
+            std::fs::metadata(dst.join("dataset.bin")).unwrap().len(),
+            1 << 20
+        );
+    }
+
+    #[test]
+    fn test_copy_bandwidth_limit() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let (src, dst) = (root.join("backup.img"), root.join("dst"));
+        std::fs::create_dir_all(&dst).unwrap();
+        std::fs::write(&src, vec![3; 1 << 20]).unwrap();
+
+        let options = TransferOptions {
+            bandwidth_limit: Some(256 * 1024),
+            ..Default::default()
+        };
+        let config = add_new_session(true, options);
+        let items = vec![PitouFile::without_metadata(PitouFilePath::from_pathbuf(
+            src.clone(),
+        ))];
+        config.begin_transfer(Arc::new(items), PitouFilePath::from_pathbuf(dst.clone()));
+        // the copy takes four seconds at this limit, so it is still going once it has started
+        testing::wait_for(
+            || get_session_with_id(config.id).unwrap().details().0,
+            |state| matches!(state, TransferState::Active(size) if size.current > 0),
+        );
+        let msg = get_session_with_id(config.id).unwrap();
+        assert!(!msg.is_terminated());
+        assert_eq!(msg.bandwidth_limit(), Some(256 * 1024));
+
+        assert!(set_session_bandwidth_limit(config.id, None));
+        let msg = wait_for(config.id);
+        assert_eq!(msg.bandwidth_limit(), None);
+        assert_eq!(
+            std::fs::read(dst.join("backup.img")).unwrap(),
+            vec![3; 1 << 20]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_following_link_cycle() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let (src, dst) = (root.join("src"), root.join("dst"));
+        std::fs::create_dir_all(src.join("nested")).unwrap();
+        std::fs::create_dir_all(&dst).unwrap();
+        std::fs::write(src.join("nested").join("notes.txt"), b"notes").unwrap();
+        std::os::unix::fs::symlink("..", src.join("nested").join("up")).unwrap();
+        std::os::unix::fs::symlink("missing", src.join("dangling")).unwrap();
+
+        let items = vec![PitouFile::without_metadata(PitouFilePath::from_pathbuf(
+            src.clone(),
+        ))];
+        let plan = plan::plan(&items, &dst, true, &TransferOptions::default());
+        assert_eq!((plan.files, plan.dirs), (2, 2));
+
+        let config = add_new_session(true, TransferOptions::default());
+        config.begin_transfer(Arc::new(items), PitouFilePath::from_pathbuf(dst.clone()));
+        let msg = wait_for(config.id);
+        assert_eq!(msg.progress().files_done, 2);
+
+        let copied = dst.join("src");
+        assert!(copied.join("nested").join("notes.txt").is_file());
+        assert!(!copied.join("nested").join("up").exists());
+        assert_eq!(
+            std::fs::read_link(copied.join("dangling")).unwrap(),
+            PathBuf::from("missing")
+        );
+    }
+}
+
+struct AllItemsCopySesssion {
+    config: Arc<TransferConfig>,
+    /// each item along with the folder it goes to
+    items: Vec<(Arc<PathBuf>, Arc<PathBuf>)>,
+}
+
+impl AllItemsCopySesssion {
+    fn init(config: Arc<TransferConfig>, items: Vec<(PathBuf, PathBuf)>) {
+        let config2 = config.clone();
+        let items = std::thread::scope(move |s| {
+            let mut handles = Vec::with_capacity(items.len());
+            for (path, dst) in items {
+                let config = config.clone();
+                let shc = s.spawn(move || (Self::compute_size(path, config), dst));
+                handles.push(shc)
+            }
+            handles
+                .into_iter()
+                .map(|v| v.join().unwrap())
+                .map(|(src, dst)| (Arc::new(src), Arc::new(dst)))
+                .collect::<Vec<_>>()
+        });
+        let config = config2;
+        config.state.lock().unwrap().end_init();
+        let session = Self { config, items };
+
+        session.config.start_now();
+
+        session.proceed();
+    }
+
+    fn proceed(self) {
+        let Self { config, items } = self;
+        let mut handles = Vec::with_capacity(items.len());
+        for (item, dst) in items {
+            let config = config.clone();
+            let src = item.clone();
+            let hdl = std::thread::spawn(move || {
+                copy_item(config.clone(), (*src).clone(), dst, &[])
+                    .map_err(|e| config.record_error(&src, e.to_string()))
+                    .is_ok()
+            });
+            handles.push((item, hdl));
+        }
+
+        thread::spawn(move || {
+            let total = handles.len();
+            let mut failed = 0;
+            for (item, handle) in handles {
+                match handle.join() {
+                    Ok(true) => (),
+                    Ok(false) => failed += 1,
+                    Err(_) => {
+                        config
+                            .record_error(&item, String::from("the transfer stopped unexpectedly"));
+                        failed += 1;
+                    }
+                }
+            }
+            config.terminate_now(failed, total);
+        });
+    }
+
+    fn compute_size(item: PathBuf, config: Arc<TransferConfig>) -> PathBuf {
+        Self::append_size(&item, &config, &mut Vec::new());
+        item
+    }
+
+    /// `ancestors` holds the folders currently being walked, which must agree with what `copy_item` skips
+    fn append_size(item: &Path, config: &TransferConfig, ancestors: &mut Vec<FileId>) {
+        // items that cannot be read are reported when the copy reaches them
+        let Ok(entry) = links::inspect(item, &config.options) else {
+            return;
+        };
+        let size = match entry {
+            Entry::Dir(id) => {
+                if ancestors.contains(&id) {
+                    return;
+                }
+                ancestors.push(id);
+                for entry in std::fs::read_dir(item).into_iter().flatten().flatten() {
+                    Self::append_size(&entry.path(), config, ancestors);
+                }
+                ancestors.pop();
+                HYPOTHETICAL_FOLDER_SIZE
+            }
+            Entry::File(len) | Entry::Link(len) => {
+                config.progress.lock().unwrap().count_file();
+                len
+            }
+            Entry::Special(_) => {
+                config.progress.lock().unwrap().count_file();
+                0
+            }
+            Entry::Skip => return,
+        };
+        config.state.lock().unwrap().append_total(size);
+    }
+}
+
+/// Copies a single item of any kind into the `dst` folder. `ancestors` are the folders the item is being copied from.
+fn copy_item(
+    config: Arc<TransferConfig>,
+    src: PathBuf,
+    dst: Arc<PathBuf>,
+    ancestors: &[FileId],
+) -> std::io::Result<()> {
+    match links::inspect(&src, &config.options)? {
+        // a link leading back into the tree being copied would recurse forever
+        Entry::Dir(id) if ancestors.contains(&id) => Ok(()),
+        Entry::Dir(id) => {
+            let ancestors = [ancestors, &[id]].concat();
+            CopyFolderSession::new(config, src, dst, ancestors).proceed()
+        }
+        Entry::File(_) => CopyFileSession::new(config, src, dst)?.proceed(),
+        Entry::Link(_) => copy_link(&config, &src, &dst),
+        Entry::Special(metadata) => copy_special(&config, &src, &metadata, &dst),
+        Entry::Skip => Ok(()),
+    }
+}
+
+fn copy_special(
+    config: &TransferConfig,
+    src: &Path,
+    metadata: &std::fs::Metadata,
+    dst: &Path,
+) -> std::io::Result<()> {
+    let dst = dst_real(src, dst);
+    links::recreate_special(metadata, &dst)?;
+    preserve::apply(src, metadata, &dst, config.options.preserve)?;
+    config.progress.lock().unwrap().finish_file();
+    Ok(())
+}
+
+fn copy_link(config: &TransferConfig, src: &Path, dst: &Path) -> std::io::Result<()> {
+    let size = std::fs::symlink_metadata(src)?.len();
+    preserve::copy_link(src, &dst_real(src, dst), config.options.preserve)?;
+    config.advance(size);
+    config.progress.lock().unwrap().finish_file();
+    Ok(())
+}
+
+struct CopyFolderSession {
+    config: Arc<TransferConfig>,
+    src_folder: PathBuf,
+    dst_folder: Arc<PathBuf>,
+    ancestors: Vec<FileId>,
+}
+
+impl CopyFolderSession {
+    fn new(
+        config: Arc<TransferConfig>,
+        src_folder: PathBuf,
+        dst_folder: Arc<PathBuf>,
+        ancestors: Vec<FileId>,
+    ) -> Self {
+        Self {
+            config,
+            src_folder,
+            dst_folder,
+            ancestors,
+        }
+    }
+
+    fn proceed(self) -> Result<(), std::io::Error> {
+        let Self {
+            config,
+            src_folder,
+            dst_folder,
+            ancestors,
+        } = self;
+
+        let mut rd = std::fs::read_dir(&src_folder)?;
+        let dst_folder = Arc::new(dst_real(&src_folder, &dst_folder));
+        std::fs::create_dir(&*dst_folder)?;
+        while let Some(en) = rd.next() {
+            let elem = en?.path();
+            // one bad item must not stop the rest of the folder
+            if let Err(e) = copy_item(config.clone(), elem.clone(), dst_folder.clone(), &ancestors)
+            {
+                config.record_error(&elem, e.to_string());
+            }
+        }
+        let metadata = std::fs::metadata(&src_folder)?;
+        preserve::apply(&src_folder, &metadata, &dst_folder, config.options.preserve)?;
+        config.advance(HYPOTHETICAL_FOLDER_SIZE);
+        Ok(())
+    }
+}
+
+/// Holds a session's copies to its own and the global bandwidth limits.
+struct SessionPacer(Arc<TransferConfig>);
+
+impl Pacer for SessionPacer {
+    fn max_chunk(&self) -> Option<u64> {
+        throttle::max_chunk(&self.0.bandwidth)
+    }
+
+    fn pace(&self, bytes: u64) {
+        throttle::pace(&self.0.bandwidth, bytes)
+    }
+}
+
+struct CopyFileSession {
+    src_path: PathBuf,
+    src_file: File,
+    dst_file: File,
+    temp_dst_path: PathBuf,
+    real_dst_path: PathBuf,
+    config: Arc<TransferConfig>,
+    /// whether `dst_file` is the existing destination, updated in place instead of through a temporary file
+    in_place: bool,
+}
+
+impl CopyFileSession {
+    fn new(config: Arc<TransferConfig>, src: PathBuf, dst: Arc<PathBuf>) -> std::io::Result<Self> {
+        let real_dst_path = dst_real(&src, &dst);
+        let src_file = File::open(&src)?;
+        let in_place = config.options.changed_blocks_only
+            && std::fs::symlink_metadata(&real_dst_path).is_ok_and(|m| m.is_file());
+        let (temp_dst_path, dst_file) = if in_place {
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&real_dst_path)?;
+            (real_dst_path.clone(), file)
+        } else {
+            let temp_dst_path = dst_temp(&src, &dst);
+            let file = File::create(&temp_dst_path)?;
+            (temp_dst_path, file)
+        };
+
+        Ok(Self {
+            src_path: src,
+            src_file,
+            temp_dst_path,
+            dst_file,
+            config,
+            real_dst_path,
+            in_place,
+        })
+    }
+
+    fn proceed(&mut self) -> Result<(), std::io::Error> {
+        let name = self.src_path.file_name().unwrap_or_default();
+        let name = name.to_string_lossy();
+        self.config.progress.lock().unwrap().start_file(&name);
+        self.transfer()?;
+        self.config.progress.lock().unwrap().finish_file();
+        Ok(())
+    }
+
+    fn transfer(&mut self) -> Result<(), std::io::Error> {
+        let config = self.config.clone();
+        let pacer = SessionPacer(self.config.clone());
+        let mut progress = Progress::paced(move |cnt| config.advance(cnt), pacer);
+        if self.in_place {
+            let saved = blocks::update(&self.src_file, &self.dst_file, &mut progress)?;
+            self.config.progress.lock().unwrap().add_saved(saved);
+        } else {
+            engine::copy_contents(
+                &self.src_file,
+                &self.dst_file,
+                CopyStrategy::Auto,
+                &mut progress,
+            )?;
+        }
+        progress.flush();
+        if self.config.options.verify
+            && !verify::files_match(&self.src_path, &self.dst_file, &self.temp_dst_path)?
+        {
+            let src_path = PitouFilePath::from_pathbuf(self.src_path.clone());
+            self.config.mismatches.lock().unwrap().push(src_path);
+            // never let a corrupt copy take the place of the real file, but never delete the real file either when it
+            // was updated in place: it is kept for the next update to set right
+            if !self.in_place {
+                std::fs::remove_file(&self.temp_dst_path)?;
+            }
+            return Ok(());
+        }
+        let metadata = self.src_file.metadata()?;
+        preserve::apply(
+            &self.src_path,
+            &metadata,
+            &self.temp_dst_path,
+            self.config.options.preserve,
+        )?;
+        if self.in_place {
+            return Ok(());
+        }
+        std::fs::rename(&self.temp_dst_path, &*self.real_dst_path)
+    }
+}
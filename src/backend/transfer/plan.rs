@@ -0,0 +1,143 @@
+//! Dry runs of paste operations.
+
+use std::{fs::File, io, path::Path};
+
+use super::links::{self, Entry, FileId};
+use crate::{
+    msg::{TransferOptions, TransferPlan},
+    PitouDrive, PitouFile, PitouFilePath,
+};
+
+pub(super) fn plan(
+    items: &[PitouFile],
+    dst: &Path,
+    copy: bool,
+    options: &TransferOptions,
+) -> TransferPlan {
+    let mut plan = TransferPlan {
+        copy,
+        files: 0,
+        dirs: 0,
+        total_bytes: 0,
+        conflicts: Vec::new(),
+        permission_problems: Vec::new(),
+        free_space: None,
+        required_space: 0,
+        same_device: true,
+    };
+
+    let dst_drive = std::fs::canonicalize(dst)
+        .ok()
+        .and_then(|dst| PitouDrive::containing(&dst));
+    plan.free_space = dst_drive.as_ref().map(|d| d.free_space);
+    if !is_writable(dst) {
+        plan.permission_problems.push(dst.to_path_buf().into());
+    }
+
+    for item in items {
+        let src = &item.path.path;
+        if let Some(name) = src.file_name() {
+            let target = dst.join(name);
+            if std::fs::symlink_metadata(&target).is_ok() {
+                plan.conflicts.push(target.into());
+            }
+        }
+
+        plan.same_device &= same_device(src, dst, dst_drive.as_ref());
+
+        walk(src, options, &mut Vec::new(), &mut plan);
+
+        // moving an item out of a folder requires write access to that folder
+        if !copy && !src.parent().map(is_writable).unwrap_or(false) {
+            plan.permission_problems
+                .push(PitouFilePath::from_pathbuf(src.clone()));
+        }
+    }
+    // cut items are copied like the others, even within one drive
+    plan.required_space = plan.total_bytes;
+    plan
+}
+
+/// Walks `path` the same way a transfer with `options` would.
+fn walk(
+    path: &Path,
+    options: &TransferOptions,
+    ancestors: &mut Vec<FileId>,
+    plan: &mut TransferPlan,
+) {
+    let entry = match links::inspect(path, options) {
+        Ok(entry) => entry,
+        Err(e) => return note_error(e, path, plan),
+    };
+    match entry {
+        Entry::Dir(id) if ancestors.contains(&id) => (),
+        Entry::Dir(id) => {
+            plan.dirs += 1;
+            match std::fs::read_dir(path) {
+                Ok(rd) => {
+                    ancestors.push(id);
+                    for entry in rd.flatten() {
+                        walk(&entry.path(), options, ancestors, plan)
+                    }
+                    ancestors.pop();
+                }
+                Err(e) => note_error(e, path, plan),
+            }
+        }
+        Entry::File(len) => {
+            plan.files += 1;
+            plan.total_bytes += len;
+            if let Err(e) = File::open(path) {
+                note_error(e, path, plan)
+            }
+        }
+        Entry::Link(len) => {
+            plan.files += 1;
+            plan.total_bytes += len;
+        }
+        Entry::Special(_) => plan.files += 1,
+        Entry::Skip => (),
+    }
+}
+
+fn note_error(e: io::Error, path: &Path, plan: &mut TransferPlan) {
+    if e.kind() == io::ErrorKind::PermissionDenied {
+        plan.permission_problems.push(path.to_path_buf().into());
+    }
+}
+
+#[cfg(unix)]
+fn is_writable(path: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_writable(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .map(|m| !m.permissions().readonly())
+        .unwrap_or(false)
+}
+
+#[cfg(unix)]
+fn same_device(src: &Path, dst: &Path, _dst_drive: Option<&PitouDrive>) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (std::fs::symlink_metadata(src), std::fs::metadata(dst)) {
+        (Ok(src), Ok(dst)) => src.dev() == dst.dev(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_device(src: &Path, _dst: &Path, dst_drive: Option<&PitouDrive>) -> bool {
+    let src_drive = std::fs::canonicalize(src)
+        .ok()
+        .and_then(|src| PitouDrive::containing(&src));
+    match (src_drive, dst_drive) {
+        (Some(src), Some(dst)) => &src == dst,
+        _ => false,
+    }
+}
@@ -0,0 +1,94 @@
+//! Carries metadata over from transferred items to their copies.
+
+use std::{fs::Metadata, io, path::Path};
+
+use filetime::FileTime;
+
+use crate::msg::PreserveMetadata;
+
+/// Applies the parts of `metadata` (taken from `src`) selected by `preserve` to `dst`.
+///
+/// This must run after `dst` is fully written and, for folders, after all their children have been created,
+/// otherwise the writes that follow will bump the times again.
+pub(super) fn apply(
+    src: &Path,
+    metadata: &Metadata,
+    dst: &Path,
+    preserve: PreserveMetadata,
+) -> io::Result<()> {
+    // attributes go first since the copied permissions may no longer allow writing them
+    if preserve.xattrs {
+        copy_xattrs(src, dst)?;
+    }
+    if preserve.permissions {
+        std::fs::set_permissions(dst, metadata.permissions())?;
+    }
+    if preserve.times {
+        let atime = FileTime::from_last_access_time(metadata);
+        let mtime = FileTime::from_last_modification_time(metadata);
+        filetime::set_file_times(dst, atime, mtime)?;
+    }
+    Ok(())
+}
+
+/// Recreates the symbolic link `src` at `dst`, pointing to the same target.
+pub(super) fn copy_link(src: &Path, dst: &Path, preserve: PreserveMetadata) -> io::Result<()> {
+    let target = std::fs::read_link(src)?;
+    symlink(src, &target, dst)?;
+    if preserve.times {
+        let metadata = std::fs::symlink_metadata(src)?;
+        let atime = FileTime::from_last_access_time(&metadata);
+        let mtime = FileTime::from_last_modification_time(&metadata);
+        filetime::set_symlink_file_times(dst, atime, mtime)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(_src: &Path, target: &Path, dst: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, dst)
+}
+
+#[cfg(windows)]
+fn symlink(src: &Path, target: &Path, dst: &Path) -> io::Result<()> {
+    // windows needs to know what kind of item the link points to
+    let resolved = src.parent().map(|p| p.join(target)).unwrap_or_default();
+    if resolved.is_dir() {
+        std::os::windows::fs::symlink_dir(target, dst)
+    } else {
+        std::os::windows::fs::symlink_file(target, dst)
+    }
+}
+
+#[cfg(unix)]
+fn copy_xattrs(src: &Path, dst: &Path) -> io::Result<()> {
+    let names = match xattr::list(src) {
+        Ok(names) => names,
+        Err(e) if is_unsupported(&e) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for name in names {
+        let Some(value) = xattr::get(src, &name)? else {
+            continue;
+        };
+        match xattr::set(dst, &name, &value) {
+            // the destination filesystem cannot hold it, or it is a namespace reserved to privileged processes
+            Err(e) if is_unsupported(&e) => (),
+            res => res?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_unsupported(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::ENOTSUP | libc::EPERM | libc::EACCES)
+    )
+}
+
+#[cfg(not(unix))]
+fn copy_xattrs(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Ok(())
+}
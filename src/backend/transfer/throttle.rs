@@ -0,0 +1,161 @@
+//! Bytes-per-second limits on transfer sessions.
+//!
+//! Every session has its own limit and all sessions share a global one. Both are token buckets that may go into debt:
+//! a chunk is always let through, and the copying thread then waits until the buckets it drew from are paid back.
+
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+/// Longest single sleep, so that a changed limit is picked up promptly.
+const MAX_SLEEP: Duration = Duration::from_millis(100);
+/// A limited copy moves about this many chunks per second.
+const CHUNKS_PER_SECOND: u64 = 8;
+const MIN_CHUNK_SIZE: u64 = 16 * 1024;
+const MAX_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+pub(super) struct Bandwidth {
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    limit: Option<u64>,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(limit: Option<u64>, now: Instant) -> Self {
+        Self {
+            limit: limit.filter(|&l| l > 0),
+            tokens: 0.0,
+            refilled: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if let Some(limit) = self.limit {
+            let earned = now.duration_since(self.refilled).as_secs_f64() * limit as f64;
+            // an idle session may burst, but never by more than one chunk
+            self.tokens = (self.tokens + earned).min(chunk_size(limit) as f64);
+        }
+        self.refilled = now;
+    }
+
+    fn take(&mut self, bytes: u64, now: Instant) {
+        if self.limit.is_some() {
+            self.refill(now);
+            self.tokens -= bytes as f64;
+        }
+    }
+
+    /// How long to wait at `now` until the bucket is out of debt.
+    fn wait(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        match self.limit {
+            Some(limit) if self.tokens < 0.0 => {
+                Duration::from_secs_f64(-self.tokens / limit as f64)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+impl Bandwidth {
+    pub(super) fn new(limit: Option<u64>) -> Self {
+        Self {
+            bucket: Mutex::new(Bucket::new(limit, Instant::now())),
+        }
+    }
+
+    pub(super) fn limit(&self) -> Option<u64> {
+        self.bucket.lock().unwrap().limit
+    }
+
+    /// Replaces the limit. Any debt owed under the previous limit is forgiven. A limit of zero removes the limit.
+    pub(super) fn set_limit(&self, limit: Option<u64>) {
+        *self.bucket.lock().unwrap() = Bucket::new(limit, Instant::now());
+    }
+
+    fn take(&self, bytes: u64) {
+        self.bucket.lock().unwrap().take(bytes, Instant::now())
+    }
+
+    fn wait(&self) -> Duration {
+        self.bucket.lock().unwrap().wait(Instant::now())
+    }
+}
+
+fn global() -> &'static Bandwidth {
+    static GLOBAL: OnceLock<Bandwidth> = OnceLock::new();
+    GLOBAL.get_or_init(|| Bandwidth::new(None))
+}
+
+pub(super) fn global_limit() -> Option<u64> {
+    global().limit()
+}
+
+pub(super) fn set_global_limit(limit: Option<u64>) {
+    global().set_limit(limit)
+}
+
+/// The tightest of the session's own limit and the global one.
+pub(super) fn effective_limit(session: &Bandwidth) -> Option<u64> {
+    match (session.limit(), global_limit()) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// How many bytes a session limited by `session` should move at a time, or `None` if it is not limited at all.
+pub(super) fn max_chunk(session: &Bandwidth) -> Option<u64> {
+    effective_limit(session).map(chunk_size)
+}
+
+fn chunk_size(limit: u64) -> u64 {
+    (limit / CHUNKS_PER_SECOND).clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE)
+}
+
+/// Charges `bytes` to both the session and the global limit, then blocks until neither is in debt.
+pub(super) fn pace(session: &Bandwidth, bytes: u64) {
+    let global = global();
+    session.take(bytes);
+    global.take(bytes);
+    loop {
+        let wait = session.wait().max(global.wait());
+        if wait.is_zero() {
+            break;
+        }
+        std::thread::sleep(wait.min(MAX_SLEEP));
+    }
+}
+
+#[cfg(test)]
+mod test_mod {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut bucket = Bucket::new(Some(64 * 1024), start);
+
+        // a chunk goes through at once and is then paid back at the limit
+        bucket.take(32 * 1024, start);
+        assert_eq!(bucket.wait(start), Duration::from_millis(500));
+        assert_eq!(bucket.wait(at(200)), Duration::from_millis(300));
+        assert_eq!(bucket.wait(at(500)), Duration::ZERO);
+
+        // idling saves up no more than one chunk
+        assert_eq!(bucket.wait(at(60_000)), Duration::ZERO);
+        assert_eq!(bucket.tokens, chunk_size(64 * 1024) as f64);
+        bucket.take(chunk_size(64 * 1024) + 64 * 1024, at(60_000));
+        assert_eq!(bucket.wait(at(60_000)), Duration::from_secs(1));
+
+        // debt is forgiven along with the limit
+        let mut bucket = Bucket::new(None, at(60_000));
+        bucket.take(1 << 30, at(60_000));
+        assert_eq!(bucket.wait(at(60_000)), Duration::ZERO);
+    }
+}
@@ -0,0 +1,113 @@
+//! Per-file counters and throughput estimates reported with every `TransferMsg`.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::msg::{TransferProgress, TransferSize};
+
+/// How far back the instantaneous rate looks.
+const RATE_WINDOW: Duration = Duration::from_secs(3);
+/// Minimum spacing between two recorded samples.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(200);
+/// Weight of the newest sample in the smoothed rate used for the ETA.
+const SMOOTHING: f64 = 0.2;
+
+pub(super) struct ProgressTracker {
+    files_total: u64,
+    files_done: u64,
+    current_file: String,
+    saved_bytes: u64,
+    samples: VecDeque<(Instant, u64)>,
+    smoothed_rate: Option<f64>,
+}
+
+impl ProgressTracker {
+    pub(super) fn new() -> Self {
+        Self {
+            files_total: 0,
+            files_done: 0,
+            current_file: String::new(),
+            saved_bytes: 0,
+            samples: VecDeque::new(),
+            smoothed_rate: None,
+        }
+    }
+
+    pub(super) fn count_file(&mut self) {
+        self.files_total += 1
+    }
+
+    pub(super) fn start_file(&mut self, name: &str) {
+        name.clone_into(&mut self.current_file)
+    }
+
+    pub(super) fn finish_file(&mut self) {
+        self.files_done += 1
+    }
+
+    pub(super) fn add_saved(&mut self, bytes: u64) {
+        self.saved_bytes += bytes
+    }
+
+    pub(super) fn files_done(&self) -> u64 {
+        self.files_done
+    }
+
+    /// Records that `transferred` bytes of the session have been copied so far.
+    pub(super) fn record(&mut self, transferred: u64) {
+        let now = Instant::now();
+        match self.samples.back() {
+            None => self.samples.push_back((now, transferred)),
+            Some(&(time, bytes)) => {
+                let dt = now.duration_since(time);
+                if dt < SAMPLE_INTERVAL {
+                    return;
+                }
+                let rate = (transferred - bytes) as f64 / dt.as_secs_f64();
+                let smoothed = match self.smoothed_rate {
+                    None => rate,
+                    Some(prev) => SMOOTHING * rate + (1.0 - SMOOTHING) * prev,
+                };
+                self.smoothed_rate = Some(smoothed);
+                self.samples.push_back((now, transferred));
+            }
+        }
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) > RATE_WINDOW {
+            self.samples.pop_front();
+        }
+    }
+
+    pub(super) fn read(&self, size: Option<TransferSize>, elapsed: Duration) -> TransferProgress {
+        let mut progress = TransferProgress {
+            current_file: self.current_file.clone(),
+            files_done: self.files_done,
+            files_total: self.files_total,
+            current_rate: 0,
+            average_rate: 0,
+            eta: None,
+            saved_bytes: self.saved_bytes,
+        };
+        let Some(TransferSize { total, current }) = size else {
+            return progress;
+        };
+        if elapsed.as_secs_f64() > 0.0 {
+            progress.average_rate = (current as f64 / elapsed.as_secs_f64()) as u64;
+        }
+        if let (Some(&(first_time, first_bytes)), Some(&(_, last_bytes))) =
+            (self.samples.front(), self.samples.back())
+        {
+            // measured up to now so that a stalled transfer shows a falling rate
+            let window = first_time.elapsed().as_secs_f64();
+            if window > 0.0 {
+                progress.current_rate = ((last_bytes - first_bytes) as f64 / window) as u64;
+            }
+        }
+        if let Some(rate) = self.smoothed_rate.filter(|&r| r > 0.0) {
+            let remaining = total.saturating_sub(current) as f64;
+            progress.eta = Some(Duration::from_secs_f64(remaining / rate));
+        }
+        progress
+    }
+}
@@ -0,0 +1,48 @@
+//! Post-copy verification of transferred files.
+
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+const HASH_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Returns true if `src` and its freshly written copy `dst` hash to the same value.
+///
+/// `dst` is flushed to the device first and, on Linux, dropped from the page cache so that its hash reflects what
+/// was actually written rather than what is still sitting in memory.
+pub(super) fn files_match(src: &Path, dst: &File, dst_path: &Path) -> io::Result<bool> {
+    dst.sync_all()?;
+    #[cfg(target_os = "linux")]
+    unsafe {
+        use std::os::unix::io::AsRawFd;
+        libc::posix_fadvise(dst.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+    }
+    Ok(checksum(src)? == checksum(dst_path)?)
+}
+
+pub(crate) fn checksum(path: &Path) -> io::Result<blake3::Hash> {
+    checksum_of(File::open(path)?)
+}
+
+/// Hashes the first `len` bytes of the file at `path`.
+pub(crate) fn checksum_head(path: &Path, len: u64) -> io::Result<blake3::Hash> {
+    checksum_of(File::open(path)?.take(len))
+}
+
+fn checksum_of(mut file: impl Read) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0; HASH_BUFFER_SIZE];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(cnt) => {
+                hasher.update(&buffer[..cnt]);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(hasher.finalize())
+}
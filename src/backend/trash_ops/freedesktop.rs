@@ -0,0 +1,716 @@
+//! The freedesktop.org trash specification, version 1.0.
+//!
+//! Items on the same mount as the home trash go to `$XDG_DATA_HOME/Trash`. Items on any other mount go to a trash at
+//! the top of that mount, `$topdir/.Trash/$uid` when the administrator provided a sticky `.Trash` folder,
+//! `$topdir/.Trash-$uid` otherwise, so that deleting from a removable drive does not copy anything over to the home
+//! drive. Only when neither can be used do items fall back to the home trash, as the specification allows, and get
+//! copied there.
+
+use std::{
+    ffi::{OsStr, OsString},
+    fs::{File, Metadata},
+    io::{self, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{DirBuilderExt, MetadataExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+use chrono::TimeZone;
+
+use super::info::{self, DirectorySize, TrashInfo};
+use crate::{
+    backend::{deletion, fs_ops::drive},
+    msg::RestoreConflict,
+    PitouDateTime, PitouFilePath, PitouFileSize, PitouTrashItem, PitouTrashItemMetadata,
+};
+
+const INFO_EXTENSION: &str = "trashinfo";
+const DIRECTORY_SIZES: &str = "directorysizes";
+
+/// The set of trash directories visible to one user.
+pub(crate) struct Trash {
+    home: PathBuf,
+    topdirs: Vec<PathBuf>,
+    uid: u32,
+}
+
+/// A trash directory, holding the `files` and `info` folders.
+struct TrashDir {
+    root: PathBuf,
+    /// the mount the trash belongs to; paths in its info files are relative to it. `None` for the home trash
+    topdir: Option<PathBuf>,
+}
+
+impl TrashDir {
+    fn files(&self) -> PathBuf {
+        self.root.join("files")
+    }
+
+    fn info(&self) -> PathBuf {
+        self.root.join("info")
+    }
+
+    fn info_path(&self, name: &OsStr) -> PathBuf {
+        let mut file = name.to_os_string();
+        file.push(".");
+        file.push(INFO_EXTENSION);
+        self.info().join(file)
+    }
+
+    fn original_path(&self, stored: &Path) -> PathBuf {
+        match &self.topdir {
+            Some(topdir) if stored.is_relative() => topdir.join(stored),
+            _ => stored.to_path_buf(),
+        }
+    }
+
+    fn read_directory_sizes(&self) -> Vec<DirectorySize> {
+        std::fs::read_to_string(self.root.join(DIRECTORY_SIZES))
+            .map(|v| info::parse_directory_sizes(&v))
+            .unwrap_or_default()
+    }
+
+    /// Replaces the `directorysizes` file atomically, as other applications may be reading it.
+    fn write_directory_sizes(&self, entries: &[DirectorySize]) -> io::Result<()> {
+        let temp = self
+            .root
+            .join(format!(".{DIRECTORY_SIZES}.{}", std::process::id()));
+        std::fs::write(&temp, info::format_directory_sizes(entries))?;
+        std::fs::rename(&temp, self.root.join(DIRECTORY_SIZES))
+    }
+
+    fn forget_directory_size(&self, name: &OsStr) -> io::Result<()> {
+        let mut entries = self.read_directory_sizes();
+        let len = entries.len();
+        entries.retain(|v| v.name.as_os_str() != name);
+        if entries.len() == len {
+            return Ok(());
+        }
+        self.write_directory_sizes(&entries)
+    }
+}
+
+impl Trash {
+    pub(crate) fn new(home: PathBuf, topdirs: Vec<PathBuf>, uid: u32) -> Self {
+        Self { home, topdirs, uid }
+    }
+
+    /// The trash of the current user, with a trash directory at the top of every mount.
+    pub(crate) fn system() -> io::Result<Self> {
+        let data = dirs::data_dir().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "no data directory for the home trash",
+            )
+        })?;
+        let uid = unsafe { libc::getuid() };
+        Ok(Self::new(data.join("Trash"), drive::mount_points(), uid))
+    }
+
+    fn topdir_of(&self, path: &Path) -> Option<&Path> {
+        self.topdirs
+            .iter()
+            .filter(|v| path.starts_with(v))
+            .max_by_key(|v| v.as_os_str().len())
+            .map(|v| v.as_path())
+    }
+
+    fn home_dir(&self) -> TrashDir {
+        TrashDir {
+            root: self.home.clone(),
+            topdir: None,
+        }
+    }
+
+    /// The trash directory that an item at `path` goes to, created if needed. Items on another mount than the home
+    /// trash go to the home trash when their mount has no trash this user can have.
+    fn dir_for(&self, path: &Path) -> io::Result<TrashDir> {
+        let topdir = self.topdir_of(path);
+        if let Some(topdir) = topdir.filter(|&v| Some(v) != self.topdir_of(&self.home)) {
+            let dir = self.topdir_trash(topdir).and_then(|root| {
+                let dir = TrashDir {
+                    root,
+                    topdir: Some(topdir.to_path_buf()),
+                };
+                create_dirs(&dir).map(|_| dir)
+            });
+            if let Ok(dir) = dir {
+                return Ok(dir);
+            }
+        }
+        let dir = self.home_dir();
+        create_dirs(&dir)?;
+        Ok(dir)
+    }
+
+    fn topdir_trash(&self, topdir: &Path) -> io::Result<PathBuf> {
+        // the shared `.Trash` is only trusted if it is a real, sticky folder
+        let shared = topdir.join(".Trash");
+        if let Ok(metadata) = std::fs::symlink_metadata(&shared) {
+            if metadata.is_dir() && metadata.permissions().mode() & 0o1000 != 0 {
+                let own = shared.join(self.uid.to_string());
+                let created = std::fs::DirBuilder::new().mode(0o700).create(&own);
+                if created.is_ok() || is_own_dir(&own, self.uid) {
+                    return Ok(own);
+                }
+            }
+        }
+
+        let own = topdir.join(format!(".Trash-{}", self.uid));
+        match std::fs::DirBuilder::new().mode(0o700).create(&own) {
+            Ok(()) => Ok(own),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && is_own_dir(&own, self.uid) => {
+                Ok(own)
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} is not a trash folder of this user", own.display()),
+            )),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// All trash directories that exist, the home trash first.
+    fn dirs(&self) -> Vec<TrashDir> {
+        let mut dirs = vec![self.home_dir()];
+        for topdir in &self.topdirs {
+            let candidates = [
+                topdir.join(".Trash").join(self.uid.to_string()),
+                topdir.join(format!(".Trash-{}", self.uid)),
+            ];
+            for root in candidates {
+                if is_own_dir(&root, self.uid) && dirs.iter().all(|v| v.root != root) {
+                    dirs.push(TrashDir {
+                        root,
+                        topdir: Some(topdir.clone()),
+                    })
+                }
+            }
+        }
+        dirs.retain(|v| v.info().is_dir());
+        dirs
+    }
+
+    /// Moves the item at `path` into the trash. Returns the id of the new trash item.
+    pub(crate) fn delete(&self, path: &Path) -> io::Result<String> {
+        self.delete_with_progress(path, &mut |_| ())
+    }
+
+    /// Like [`Trash::delete`], passing `progress` the bytes of the files as they are moved. A rename moves the whole
+    /// item at once, while an item going to a trash on another mount is copied there file by file, then deleted.
+    pub(crate) fn delete_with_progress(
+        &self,
+        path: &Path,
+        progress: &mut dyn FnMut(u64),
+    ) -> io::Result<String> {
+        let path = absolute(path)?;
+        let metadata = std::fs::symlink_metadata(&path)?;
+        let dir = self.dir_for(&path)?;
+        let stored = match &dir.topdir {
+            Some(topdir) => path.strip_prefix(topdir).unwrap_or(&path).to_path_buf(),
+            None => path.clone(),
+        };
+        let info = TrashInfo {
+            path: stored,
+            deleted: chrono::Local::now().naive_local(),
+        };
+
+        let (name, info_path, mut info_file) =
+            reserve_name(&dir, path.file_name().unwrap_or_default())?;
+        let file = dir.files().join(&name);
+        let mut renamed = false;
+        let res = info_file
+            .write_all(info.format().as_bytes())
+            .and_then(|_| info_file.sync_all())
+            .and_then(|_| match std::fs::rename(&path, &file) {
+                Ok(()) => {
+                    renamed = true;
+                    Ok(())
+                }
+                Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+                    copy_all(&path, &file, progress).inspect_err(|_| _ = remove_all(&file))
+                }
+                Err(e) => Err(e),
+            });
+        if let Err(e) = res {
+            let _ = std::fs::remove_file(&info_path);
+            return Err(e);
+        }
+        // the copy is a complete trash item by now, whatever is left of the original
+        let removed = match renamed {
+            true => Ok(()),
+            false => remove_all(&path),
+        };
+
+        let mut size = metadata.len();
+        if metadata.is_dir() {
+            size = deletion::size_of(&dir.files().join(&name));
+            let mut entries = dir.read_directory_sizes();
+            entries.push(DirectorySize {
+                size,
+                mtime: std::fs::metadata(&info_path)?.mtime(),
+                name,
+            });
+            dir.write_directory_sizes(&entries)?;
+        }
+        if renamed {
+            progress(size);
+        }
+        removed?;
+        Ok(id_of(&info_path))
+    }
+
+    /// Every item in every trash directory. Items whose info file cannot be read are left out.
+    pub(crate) fn list(&self) -> Vec<PitouTrashItem> {
+        let mut items = Vec::new();
+        for dir in self.dirs() {
+            let cached = dir.read_directory_sizes();
+            let mut sizes = Vec::new();
+            let Ok(rd) = std::fs::read_dir(dir.info()) else {
+                continue;
+            };
+            for entry in rd.flatten() {
+                let info_path = entry.path();
+                if info_path.extension() != Some(OsStr::new(INFO_EXTENSION)) {
+                    continue;
+                }
+                let Some(item) = read_item(&dir, &info_path, &cached, &mut sizes) else {
+                    continue;
+                };
+                items.push(item);
+            }
+            // keeps the cache to the folders that are still in the trash, with up to date sizes
+            let stale = sizes.len() != cached.len()
+                || sizes.iter().any(|v| {
+                    !cached
+                        .iter()
+                        .any(|u| u.name == v.name && u.mtime == v.mtime && u.size == v.size)
+                });
+            if stale {
+                let _ = dir.write_directory_sizes(&sizes);
+            }
+        }
+        items
+    }
+
+    /// Finds the trash directory and the name in it of the item with `id`, refusing ids outside of this trash.
+    fn locate(&self, id: &str) -> io::Result<(TrashDir, OsString)> {
+        let info_path = PathBuf::from(info::decode(id));
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "not an item of the trash");
+        if info_path.extension() != Some(OsStr::new(INFO_EXTENSION)) {
+            return Err(invalid());
+        }
+        let name = info_path.file_stem().ok_or_else(invalid)?.to_os_string();
+        let dir = self
+            .dirs()
+            .into_iter()
+            .find(|v| info_path.parent() == Some(&v.info()))
+            .ok_or_else(invalid)?;
+        Ok((dir, name))
+    }
+
+    /// Where the item with `id` is kept inside the trash.
+    pub(crate) fn location(&self, id: &str) -> io::Result<PathBuf> {
+        let (dir, name) = self.locate(id)?;
+        Ok(dir.files().join(name))
+    }
+
+    /// Where the item with `id` was deleted from.
+    pub(crate) fn original_path(&self, id: &str) -> io::Result<PathBuf> {
+        let (dir, name) = self.locate(id)?;
+        let content = std::fs::read_to_string(dir.info_path(&name))?;
+        let info = TrashInfo::parse(&content)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed trash info"))?;
+        Ok(dir.original_path(&info.path))
+    }
+
+    /// Moves the item with `id` back to where it was deleted from, or into `destination` when given, creating missing
+    /// parent folders only once the item is known to go there. Returns where the item ended up, or `None` if it was
+    /// left in the trash because of `conflict`.
+    pub(crate) fn restore(
+        &self,
+        id: &str,
+        destination: Option<&Path>,
+        conflict: RestoreConflict,
+    ) -> io::Result<Option<PathBuf>> {
+        let original = self.original_path(id)?;
+        let (dir, name) = self.locate(id)?;
+        let mut target = match destination {
+            Some(destination) => destination.join(original.file_name().unwrap_or(&name)),
+            None => original,
+        };
+        if std::fs::symlink_metadata(&target).is_ok() {
+            match conflict {
+                RestoreConflict::Skip => return Ok(None),
+                // the item in the way stays recoverable
+                RestoreConflict::Overwrite => _ = self.delete(&target)?,
+                RestoreConflict::Rename => target = free_name(&target),
+            }
+        }
+        // the outermost parent that is missing, removed again if the item cannot be moved
+        let created = target
+            .ancestors()
+            .skip(1)
+            .take_while(|v| !v.as_os_str().is_empty() && std::fs::symlink_metadata(v).is_err())
+            .last()
+            .map(Path::to_path_buf);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = dir.files().join(&name);
+        let copied = match std::fs::rename(&file, &target) {
+            Ok(()) => Ok(false),
+            Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+                copy_all(&file, &target, &mut |_| ())
+                    .inspect_err(|_| _ = remove_all(&target))
+                    .map(|_| true)
+            }
+            Err(e) => Err(e),
+        };
+        match copied {
+            Ok(true) => remove_all(&file)?,
+            Ok(false) => (),
+            Err(e) => {
+                if let Some(created) = created {
+                    let _ = remove_all(&created);
+                }
+                return Err(e);
+            }
+        }
+        std::fs::remove_file(dir.info_path(&name))?;
+        dir.forget_directory_size(&name)?;
+        Ok(Some(target))
+    }
+
+    /// Deletes the item with `id` for good.
+    pub(crate) fn purge(&self, id: &str) -> io::Result<()> {
+        let (dir, name) = self.locate(id)?;
+        match remove_all(&dir.files().join(&name)) {
+            // only the info file is left
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            res => res?,
+        }
+        std::fs::remove_file(dir.info_path(&name))?;
+        dir.forget_directory_size(&name)
+    }
+}
+
+fn read_item(
+    dir: &TrashDir,
+    info_path: &Path,
+    cached: &[DirectorySize],
+    sizes: &mut Vec<DirectorySize>,
+) -> Option<PitouTrashItem> {
+    let name = info_path.file_stem()?.to_os_string();
+    let info = TrashInfo::parse(&std::fs::read_to_string(info_path).ok()?)?;
+    let metadata = std::fs::symlink_metadata(dir.files().join(&name)).ok()?;
+    let size = if metadata.is_dir() {
+        let mtime = std::fs::metadata(info_path).ok()?.mtime();
+        let size = cached
+            .iter()
+            .find(|v| v.name == name && v.mtime == mtime)
+            .map(|v| v.size)
+            .unwrap_or_else(|| deletion::size_of(&dir.files().join(&name)));
+        sizes.push(DirectorySize { size, mtime, name });
+        size
+    } else {
+        metadata.len()
+    };
+    let deleted = chrono::Local
+        .from_local_datetime(&info.deleted)
+        .earliest()
+        .map(|v| v.naive_utc())
+        .unwrap_or(info.deleted);
+
+    Some(PitouTrashItem {
+        original_path: PitouFilePath::from_pathbuf(dir.original_path(&info.path)),
+        metadata: PitouTrashItemMetadata {
+            id: id_of(info_path),
+            deleted: PitouDateTime { datetime: deleted },
+            size: PitouFileSize::new(size),
+            is_dir: metadata.is_dir(),
+        },
+    })
+}
+
+/// Claims a name in `dir` for an item called `name` by creating its info file, which fails if another process got to
+/// it first. Returns the name, the path of the info file and the info file itself.
+fn reserve_name(dir: &TrashDir, name: &OsStr) -> io::Result<(OsString, PathBuf, File)> {
+    for n in 1.. {
+        let candidate = if n == 1 {
+            name.to_os_string()
+        } else {
+            let mut candidate = name.to_os_string();
+            candidate.push(format!(".{n}"));
+            candidate
+        };
+        let info_path = dir.info_path(&candidate);
+        let file = match File::options()
+            .write(true)
+            .create_new(true)
+            .open(&info_path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+        // an orphan in `files` would be overwritten by the rename
+        if std::fs::symlink_metadata(dir.files().join(&candidate)).is_ok() {
+            std::fs::remove_file(&info_path)?;
+            continue;
+        }
+        return Ok((candidate, info_path, file));
+    }
+    unreachable!()
+}
+
+/// The first of `name (2).ext`, `name (3).ext`, ... next to `path` that nothing occupies.
+fn free_name(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default();
+    let extension = path.extension();
+    for n in 2.. {
+        let mut name = stem.to_os_string();
+        name.push(format!(" ({n})"));
+        if let Some(extension) = extension {
+            name.push(".");
+            name.push(extension);
+        }
+        let candidate = path.with_file_name(name);
+        if std::fs::symlink_metadata(&candidate).is_err() {
+            return candidate;
+        }
+    }
+    unreachable!()
+}
+
+fn remove_all(path: &Path) -> io::Result<()> {
+    if std::fs::symlink_metadata(path)?.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+/// Copies `src` to `dst` without following links, for moves that cross mounts, passing `progress` the bytes of each
+/// file copied.
+fn copy_all(src: &Path, dst: &Path, progress: &mut dyn FnMut(u64)) -> io::Result<()> {
+    let metadata = std::fs::symlink_metadata(src)?;
+    if metadata.is_symlink() {
+        std::os::unix::fs::symlink(std::fs::read_link(src)?, dst)
+    } else if metadata.is_dir() {
+        std::fs::create_dir(dst)?;
+        for entry in std::fs::read_dir(src)? {
+            let entry = entry?;
+            copy_all(&entry.path(), &dst.join(entry.file_name()), progress)?;
+        }
+        std::fs::set_permissions(dst, metadata.permissions())
+    } else {
+        std::fs::copy(src, dst).map(progress)
+    }
+}
+
+fn create_dirs(dir: &TrashDir) -> io::Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true).mode(0o700);
+    builder.create(dir.files())?;
+    builder.create(dir.info())
+}
+
+/// The absolute form of `path`, resolving links in its parent but not the item itself.
+fn absolute(path: &Path) -> io::Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "cannot trash this path"))?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Ok(std::fs::canonicalize(parent)?.join(name))
+}
+
+fn is_own_dir(path: &Path, uid: u32) -> bool {
+    std::fs::symlink_metadata(path).is_ok_and(|m: Metadata| m.is_dir() && m.uid() == uid)
+}
+
+/// Ids are info file paths, percent-encoded so that any path fits in a string.
+fn id_of(info_path: &Path) -> String {
+    info::encode(info_path.as_os_str().as_bytes())
+}
+
+#[cfg(test)]
+mod test_mod {
+    use super::*;
+
+    fn test_trash(root: &Path) -> Trash {
+        let home = root.join("home").join(".local").join("share").join("Trash");
+        let uid = unsafe { libc::getuid() };
+        Trash::new(home, vec![root.to_path_buf(), root.join("usb")], uid)
+    }
+
+    #[test]
+    fn test_home_trash() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        std::fs::create_dir_all(root.join("home").join("notes")).unwrap();
+        let original = root.join("home").join("notes").join("to do.txt");
+        std::fs::write(&original, b"milk").unwrap();
+        let trash = test_trash(root);
+
+        let id = trash.delete(&original).unwrap();
+        assert!(!original.exists());
+        let info = trash.home.join("info").join("to do.txt.trashinfo");
+        let content = std::fs::read_to_string(info).unwrap();
+        assert!(content.contains("Path=/") && content.contains("to%20do.txt"));
+
+        let items = trash.list();
+        assert_eq!(items.len(), 1);
+        let location = trash.location(&id).unwrap();
+        assert!(location == trash.home.join("files").join("to do.txt"));
+        assert!(items[0].original_path.path == original);
+        assert_eq!((items[0].id(), items[0].metadata.size.bytes), (&*id, 4));
+
+        let restored = trash.restore(&id, None, RestoreConflict::Skip).unwrap();
+        assert!(restored.as_ref() == Some(&original));
+        assert_eq!(std::fs::read(&original).unwrap(), b"milk");
+        assert!(trash.list().is_empty());
+    }
+
+    #[test]
+    fn test_topdir_trash() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let photos = root.join("usb").join("photos");
+        std::fs::create_dir_all(&photos).unwrap();
+        std::fs::write(photos.join("beach.jpg"), [0; 100]).unwrap();
+        let trash = test_trash(root);
+
+        let first = trash.delete(&photos).unwrap();
+        std::fs::create_dir_all(&photos).unwrap();
+        let second = trash.delete(&photos).unwrap();
+
+        // the removable drive keeps its own trash, with paths relative to its top
+        let own = root.join("usb").join(format!(".Trash-{}", trash.uid));
+        assert!(own.join("files").join("photos").join("beach.jpg").is_file());
+        assert!(own.join("files").join("photos.2").is_dir());
+        let content = std::fs::read_to_string(own.join("info").join("photos.trashinfo")).unwrap();
+        assert!(content.contains("Path=photos\n"));
+        let sizes = std::fs::read_to_string(own.join("directorysizes")).unwrap();
+        assert!(sizes.starts_with("100 ") && sizes.contains(" photos\n"));
+
+        let mut items = trash.list();
+        items.sort_by_key(|v| v.metadata.size.bytes);
+        assert_eq!(items.len(), 2);
+        assert!(items
+            .iter()
+            .all(|v| v.original_path.path == photos && v.is_dir()));
+        assert_eq!(items[1].metadata.size.bytes, 100);
+
+        trash.purge(&first).unwrap();
+        trash.purge(&second).unwrap();
+        assert!(trash.list().is_empty());
+        assert_eq!(
+            std::fs::read_to_string(own.join("directorysizes")).unwrap(),
+            ""
+        );
+        assert!(trash.purge(&first).is_err());
+    }
+
+    #[test]
+    fn test_home_trash_fallback() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        // another mount makes the move a copy, when there is one to test with
+        let drive = tempfile::tempdir_in("/dev/shm")
+            .or_else(|_| tempfile::tempdir_in(root))
+            .unwrap();
+        let usb = drive.path();
+        let photos = usb.join("photos");
+        std::fs::create_dir_all(photos.join("raw")).unwrap();
+        std::fs::write(photos.join("beach.jpg"), [0; 100]).unwrap();
+        std::fs::write(photos.join("raw").join("beach.dng"), [0; 50]).unwrap();
+        let home = root.join("home").join(".local").join("share").join("Trash");
+        let uid = unsafe { libc::getuid() };
+        let trash = Trash::new(
+            home.clone(),
+            vec![root.to_path_buf(), usb.to_path_buf()],
+            uid,
+        );
+        // a file where the drive's own trash would go leaves no trash on the drive
+        std::fs::write(usb.join(format!(".Trash-{uid}")), "").unwrap();
+
+        let mut reported = 0;
+        let id = trash
+            .delete_with_progress(&photos, &mut |cnt| reported += cnt)
+            .unwrap();
+        assert_eq!(reported, 150);
+        assert!(!photos.exists());
+        let files = home.join("files").join("photos");
+        assert!(files.join("raw").join("beach.dng").is_file());
+        let content = std::fs::read_to_string(home.join("info").join("photos.trashinfo")).unwrap();
+        assert!(content.contains(&format!("Path={}\n", photos.display())));
+
+        trash.restore(&id, None, RestoreConflict::Skip).unwrap();
+        assert_eq!(std::fs::read(photos.join("beach.jpg")).unwrap(), [0; 100]);
+        assert!(trash.list().is_empty());
+    }
+
+    #[test]
+    fn test_restore_conflicts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let notes = root.join("home").join("notes");
+        std::fs::create_dir_all(&notes).unwrap();
+        let original = notes.join("list.txt");
+        let trash = test_trash(root);
+        let mut ids = Vec::new();
+        for content in ["first", "second", "third"] {
+            std::fs::write(&original, content).unwrap();
+            ids.push(trash.delete(&original).unwrap());
+        }
+        std::fs::write(&original, "current").unwrap();
+
+        let skipped = trash.restore(&ids[0], None, RestoreConflict::Skip).unwrap();
+        assert!(skipped.is_none() && trash.list().len() == 3);
+        let renamed = trash
+            .restore(&ids[0], None, RestoreConflict::Rename)
+            .unwrap();
+        assert!(renamed == Some(notes.join("list (2).txt")));
+        assert_eq!(
+            std::fs::read_to_string(notes.join("list (2).txt")).unwrap(),
+            "first"
+        );
+
+        // the replaced item goes to the trash in place of the restored one
+        trash
+            .restore(&ids[1], None, RestoreConflict::Overwrite)
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&original).unwrap(), "second");
+        assert_eq!(trash.list().len(), 2);
+
+        // missing parents are created, both for the original place and for a chosen one
+        std::fs::remove_dir_all(&notes).unwrap();
+        let elsewhere = root.join("home").join("restored").join("here");
+        let restored = trash.restore(&ids[2], Some(&elsewhere), RestoreConflict::Rename);
+        assert!(restored.unwrap() == Some(elsewhere.join("list.txt")));
+        let current = trash.list().pop().unwrap();
+        trash
+            .restore(current.id(), None, RestoreConflict::Rename)
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&original).unwrap(), "current");
+        assert!(trash.list().is_empty());
+
+        // a skipped restore or one that fails leaves no folders behind
+        let id = trash.delete(&original).unwrap();
+        std::fs::write(&original, "current").unwrap();
+        let skipped = trash.restore(&id, None, RestoreConflict::Skip).unwrap();
+        assert!(skipped.is_none());
+        let (dir, name) = trash.locate(&id).unwrap();
+        std::fs::remove_file(dir.files().join(name)).unwrap();
+        let missing = root.join("home").join("missing");
+        let restored = trash.restore(&id, Some(&missing.join("here")), RestoreConflict::Rename);
+        assert!(restored.is_err() && !missing.exists());
+    }
+}
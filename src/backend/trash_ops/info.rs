@@ -0,0 +1,126 @@
+//! The `.trashinfo` and `directorysizes` files of the freedesktop trash specification.
+
+use std::{
+    ffi::OsString,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::PathBuf,
+};
+
+use chrono::NaiveDateTime;
+
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+pub(super) struct TrashInfo {
+    /// absolute, or relative to the top directory of the trash it is in
+    pub(super) path: PathBuf,
+    /// local time
+    pub(super) deleted: NaiveDateTime,
+}
+
+impl TrashInfo {
+    pub(super) fn parse(content: &str) -> Option<Self> {
+        let mut lines = content.lines().map(str::trim);
+        lines.find(|line| *line == "[Trash Info]")?;
+        let (mut path, mut deleted) = (None, None);
+        for line in lines {
+            // another group starts
+            if line.starts_with('[') {
+                break;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match key.trim() {
+                "Path" => path = Some(PathBuf::from(decode(value.trim()))),
+                // some implementations append fractions of seconds or an offset
+                "DeletionDate" => {
+                    let value = value.trim();
+                    deleted = value
+                        .get(..19)
+                        .and_then(|v| NaiveDateTime::parse_from_str(v, DATE_FORMAT).ok());
+                }
+                _ => (),
+            }
+        }
+        Some(Self {
+            path: path?,
+            deleted: deleted?,
+        })
+    }
+
+    pub(super) fn format(&self) -> String {
+        format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encode(self.path.as_os_str().as_bytes()),
+            self.deleted.format(DATE_FORMAT)
+        )
+    }
+}
+
+/// Percent-encodes everything but unreserved characters and slashes, as file URIs do.
+pub(super) fn encode(bytes: &[u8]) -> String {
+    let mut res = String::with_capacity(bytes.len());
+    for &b in bytes {
+        if b.is_ascii_alphanumeric() || b"-_.~/".contains(&b) {
+            res.push(b as char)
+        } else {
+            res.push_str(&format!("%{b:02X}"))
+        }
+    }
+    res
+}
+
+pub(super) fn decode(value: &str) -> OsString {
+    let bytes = value.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let code = (bytes[idx] == b'%')
+            .then(|| bytes.get(idx + 1..idx + 3))
+            .flatten()
+            .and_then(|v| u8::from_str_radix(std::str::from_utf8(v).ok()?, 16).ok());
+        match code {
+            Some(code) => {
+                res.push(code);
+                idx += 3;
+            }
+            None => {
+                res.push(bytes[idx]);
+                idx += 1;
+            }
+        }
+    }
+    OsString::from_vec(res)
+}
+
+/// One line of `directorysizes`: the size of a trashed folder, valid while its info file keeps `mtime`.
+pub(super) struct DirectorySize {
+    pub(super) size: u64,
+    pub(super) mtime: i64,
+    /// name of the folder inside the `files` directory
+    pub(super) name: OsString,
+}
+
+pub(super) fn parse_directory_sizes(content: &str) -> Vec<DirectorySize> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            Some(DirectorySize {
+                size: fields.next()?.parse().ok()?,
+                mtime: fields.next()?.parse().ok()?,
+                name: decode(fields.next()?),
+            })
+        })
+        .collect()
+}
+
+pub(super) fn format_directory_sizes(entries: &[DirectorySize]) -> String {
+    entries
+        .iter()
+        .map(|v| {
+            let name = encode(v.name.as_bytes());
+            format!("{} {} {}\n", v.size, v.mtime, name)
+        })
+        .collect()
+}
@@ -0,0 +1,62 @@
+//! Trash handling. On Linux and the BSDs the freedesktop.org trash specification is implemented here, everywhere else
+//! the system trash is reached through the `trash` crate.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{msg::RestoreOptions, PitouTrashItem};
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod freedesktop;
+#[cfg(all(unix, not(target_os = "macos")))]
+mod info;
+mod preview;
+mod query;
+mod retention;
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+mod system;
+
+pub(crate) use preview::preview;
+pub(crate) use query::query;
+pub(crate) use retention::{expired, stats};
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub(crate) use freedesktop::Trash;
+
+/// Moves the item at `path` to the trash, passing `progress` the bytes of the files as they are moved, where the trash
+/// can tell.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub(crate) fn delete(path: &Path, progress: &mut dyn FnMut(u64)) -> io::Result<()> {
+    Trash::system()?
+        .delete_with_progress(path, progress)
+        .map(|_| ())
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub(crate) fn list() -> io::Result<Vec<PitouTrashItem>> {
+    Ok(Trash::system()?.list())
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub(crate) fn restore(
+    item: &PitouTrashItem,
+    options: &RestoreOptions,
+) -> io::Result<Option<PathBuf>> {
+    let destination = options.destination.as_ref().map(|v| v.path.as_path());
+    Trash::system()?.restore(item.id(), destination, options.conflict)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub(crate) fn location(item: &PitouTrashItem) -> io::Result<PathBuf> {
+    Trash::system()?.location(item.id())
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub(crate) fn purge(item: &PitouTrashItem) -> io::Result<()> {
+    Trash::system()?.purge(item.id())
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub(crate) use system::{delete, list, location, purge, restore};
@@ -0,0 +1,174 @@
+//! Previews of items inside the trash store.
+
+use std::{
+    fs::File,
+    io::{self, Cursor, Read},
+    path::Path,
+};
+
+use image::{ImageFormat, ImageReader, Limits};
+
+use crate::{DirChild, PitouFileMetadata, TrashPreview};
+
+/// Bytes of a text file shown in its preview.
+const TEXT_HEAD: u64 = 16 * 1024;
+/// Image files larger than this are not decoded.
+const IMAGE_LIMIT: u64 = 32 * 1024 * 1024;
+/// Memory that decoding an image may take.
+const DECODE_LIMIT: u64 = 256 * 1024 * 1024;
+/// Pixels on the longer side of a thumbnail.
+const THUMBNAIL_SIZE: u32 = 256;
+
+const IMAGE_SIGNATURES: [(&[u8], ImageFormat); 6] = [
+    (b"\x89PNG\r\n\x1a\n", ImageFormat::Png),
+    (b"\xff\xd8\xff", ImageFormat::Jpeg),
+    (b"GIF87a", ImageFormat::Gif),
+    (b"GIF89a", ImageFormat::Gif),
+    (b"BM", ImageFormat::Bmp),
+    (b"\0\0\x01\0", ImageFormat::Ico),
+];
+
+/// Previews the item at `path`, which is its location inside the trash. Links are not followed.
+pub(crate) fn preview(path: &Path) -> io::Result<TrashPreview> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        let mut children = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+            children.push(DirChild {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                metadata: entry.metadata().ok().map(PitouFileMetadata::from),
+            });
+        }
+        return Ok(TrashPreview::Directory(children));
+    }
+    if !metadata.is_file() {
+        return Ok(TrashPreview::Unavailable);
+    }
+
+    let mut head = Vec::new();
+    File::open(path)?.take(TEXT_HEAD).read_to_end(&mut head)?;
+    if let Some(format) = image_type(&head) {
+        if metadata.len() > IMAGE_LIMIT {
+            return Ok(TrashPreview::Unavailable);
+        }
+        return Ok(thumbnail(path, format).unwrap_or(TrashPreview::Unavailable));
+    }
+    Ok(text(head, metadata.len()).unwrap_or(TrashPreview::Unavailable))
+}
+
+fn image_type(head: &[u8]) -> Option<ImageFormat> {
+    if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        return Some(ImageFormat::WebP);
+    }
+    IMAGE_SIGNATURES
+        .iter()
+        .find(|(signature, _)| head.starts_with(signature))
+        .map(|(_, format)| *format)
+}
+
+/// Decodes the image at `path` and scales it down to fit [`THUMBNAIL_SIZE`], as a PNG. `None` if it cannot be decoded.
+fn thumbnail(path: &Path, format: ImageFormat) -> Option<TrashPreview> {
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(DECODE_LIMIT);
+    let mut reader = ImageReader::open(path).ok()?;
+    reader.set_format(format);
+    reader.limits(limits);
+    let image = reader.decode().ok()?;
+    let thumbnail = match image.width().max(image.height()) > THUMBNAIL_SIZE {
+        true => image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE),
+        false => image,
+    };
+    let mut bytes = Vec::new();
+    thumbnail
+        .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+        .ok()?;
+    Some(TrashPreview::Image {
+        mime: ImageFormat::Png.to_mime_type().to_owned(),
+        bytes,
+    })
+}
+
+/// Reads `head` as UTF-8 text, tolerating a character cut off at its end.
+fn text(mut head: Vec<u8>, len: u64) -> Option<TrashPreview> {
+    if head.contains(&0) {
+        return None;
+    }
+    let truncated = len > head.len() as u64;
+    if let Err(e) = std::str::from_utf8(&head) {
+        if e.error_len().is_some() {
+            return None;
+        }
+        head.truncate(e.valid_up_to());
+    }
+    Some(TrashPreview::Text {
+        head: String::from_utf8(head).ok()?,
+        truncated,
+    })
+}
+
+#[cfg(test)]
+mod test_mod {
+    use super::*;
+
+    #[test]
+    fn test_preview() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        std::fs::create_dir_all(root.join("folder")).unwrap();
+        std::fs::write(root.join("folder").join("inner.txt"), b"").unwrap();
+
+        // a multibyte character cut in half at the end of the head
+        let mut long = vec![b'a'; TEXT_HEAD as usize - 1];
+        long.extend("é and more".as_bytes());
+        std::fs::write(root.join("long.txt"), &long).unwrap();
+        match preview(&root.join("long.txt")).unwrap() {
+            TrashPreview::Text { head, truncated } => {
+                assert!(truncated && head.len() == TEXT_HEAD as usize - 1)
+            }
+            _ => panic!("expected text"),
+        }
+
+        // a large photo comes back scaled down, keeping its aspect ratio
+        let photo = image::RgbImage::from_fn(1200, 600, |x, y| image::Rgb([x as u8, y as u8, 0]));
+        photo.save(root.join("photo.jpg")).unwrap();
+        match preview(&root.join("photo.jpg")).unwrap() {
+            TrashPreview::Image { mime, bytes } => {
+                assert_eq!(mime, "image/png");
+                let thumbnail = image::load_from_memory(&bytes).unwrap();
+                assert_eq!((thumbnail.width(), thumbnail.height()), (256, 128));
+            }
+            _ => panic!("expected an image"),
+        }
+        image::GrayImage::new(3, 2)
+            .save(root.join("dot.png"))
+            .unwrap();
+        match preview(&root.join("dot.png")).unwrap() {
+            TrashPreview::Image { bytes, .. } => {
+                let thumbnail = image::load_from_memory(&bytes).unwrap();
+                assert_eq!((thumbnail.width(), thumbnail.height()), (3, 2));
+            }
+            _ => panic!("expected an image"),
+        }
+        // a file that only looks like an image
+        std::fs::write(root.join("broken.png"), b"\x89PNG\r\n\x1a\n0000").unwrap();
+        assert!(matches!(
+            preview(&root.join("broken.png")).unwrap(),
+            TrashPreview::Unavailable
+        ));
+
+        std::fs::write(root.join("data.bin"), [7, 0, 1]).unwrap();
+        assert!(matches!(
+            preview(&root.join("data.bin")).unwrap(),
+            TrashPreview::Unavailable
+        ));
+
+        match preview(&root.join("folder")).unwrap() {
+            TrashPreview::Directory(children) => {
+                assert_eq!(children.len(), 1);
+                assert_eq!(children[0].name(), "inner.txt");
+            }
+            _ => panic!("expected a listing"),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use crate::{backend::search::SearchType, PitouTrashItem, TrashQuery};
+
+/// The items of `items` that satisfy `query`, in the order it asks for. `None` if its pattern is not a valid regex.
+pub(crate) fn query(items: Vec<PitouTrashItem>, query: &TrashQuery) -> Option<Vec<PitouTrashItem>> {
+    let pattern = match query.input.is_empty() {
+        true => None,
+        false => Some(SearchType::parse_if_regex(
+            query.search_kind,
+            query.input.clone(),
+        )?),
+    };
+    let items = items
+        .into_iter()
+        .filter(|item| {
+            let metadata = &item.metadata;
+            pattern
+                .as_ref()
+                .is_none_or(|v| v.matches(item.name(), query.case_sensitive))
+                && query.original_dir.as_ref().is_none_or(|dir| {
+                    item.original_path
+                        .path
+                        .parent()
+                        .is_some_and(|v| v.starts_with(&dir.path))
+                })
+                && query
+                    .deleted_after
+                    .is_none_or(|v| metadata.deleted.datetime >= v.datetime)
+                && query
+                    .deleted_before
+                    .is_none_or(|v| metadata.deleted.datetime <= v.datetime)
+                && query.min_size.is_none_or(|v| metadata.size.bytes >= v)
+                && query.max_size.is_none_or(|v| metadata.size.bytes <= v)
+        })
+        .collect();
+    match query.sort {
+        Some(sort) => Some(sort.sorted(items)),
+        None => Some(items),
+    }
+}
+
+#[cfg(test)]
+mod test_mod {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{
+        PitouDateTime, PitouFilePath, PitouFileSize, PitouFileSortOrder, PitouTrashItemMetadata,
+        TrashSort,
+    };
+
+    #[test]
+    fn test_trash_query() {
+        let now = chrono::Utc::now().naive_utc();
+        let items = || {
+            [
+                ("/home/docs/Report.pdf", 3, 900),
+                ("/home/docs/old/report.txt", 9, 20),
+                ("/tmp/notes.txt", 1, 40),
+            ]
+            .into_iter()
+            .map(|(path, days_ago, size)| PitouTrashItem {
+                original_path: PitouFilePath::from_pathbuf(PathBuf::from(path)),
+                metadata: PitouTrashItemMetadata {
+                    id: path.to_owned(),
+                    deleted: PitouDateTime {
+                        datetime: now - chrono::Duration::days(days_ago),
+                    },
+                    size: PitouFileSize::new(size),
+                    is_dir: false,
+                },
+            })
+            .collect::<Vec<_>>()
+        };
+        let ids = |v: Vec<PitouTrashItem>| v.into_iter().map(|u| u.metadata.id).collect::<Vec<_>>();
+
+        let by_name = TrashQuery {
+            input: "report".to_owned(),
+            search_kind: 1,
+            sort: Some(TrashSort::Size(PitouFileSortOrder::Decreasing)),
+            ..Default::default()
+        };
+        let found = ids(query(items(), &by_name).unwrap());
+        assert_eq!(
+            found,
+            ["/home/docs/Report.pdf", "/home/docs/old/report.txt"]
+        );
+
+        let by_place = TrashQuery {
+            original_dir: Some(PitouFilePath::from_pathbuf(PathBuf::from("/home/docs"))),
+            deleted_after: Some(PitouDateTime {
+                datetime: now - chrono::Duration::days(5),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(query(items(), &by_place).unwrap()),
+            ["/home/docs/Report.pdf"]
+        );
+
+        let by_size = TrashQuery {
+            input: r"\.txt$".to_owned(),
+            min_size: Some(30),
+            ..Default::default()
+        };
+        assert_eq!(ids(query(items(), &by_size).unwrap()), ["/tmp/notes.txt"]);
+
+        let invalid = TrashQuery {
+            input: "(".to_owned(),
+            ..Default::default()
+        };
+        assert!(query(items(), &invalid).is_none());
+    }
+}
@@ -0,0 +1,125 @@
+//! Statistics over the trash and the selection of items that a [`TrashRetention`] wants purged.
+
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDateTime;
+
+use crate::{PitouFilePath, PitouFileSize, PitouTrashItem, TrashRetention, TrashVolumeStats};
+
+/// Totals of `items` per volume, a volume being the longest of `mounts` that holds the original path of an item.
+pub(crate) fn stats(items: &[PitouTrashItem], mounts: &[PathBuf]) -> Vec<TrashVolumeStats> {
+    let mut res: Vec<TrashVolumeStats> = Vec::new();
+    for item in items {
+        let volume = volume_of(&item.original_path.path, mounts);
+        let idx = match res.iter().position(|v| v.volume.path == volume) {
+            Some(idx) => idx,
+            None => {
+                res.push(TrashVolumeStats {
+                    volume: PitouFilePath::from_pathbuf(volume),
+                    items: 0,
+                    size: PitouFileSize::new(0),
+                    oldest: None,
+                });
+                res.len() - 1
+            }
+        };
+        let stats = &mut res[idx];
+        stats.items += 1;
+        stats.size.bytes += item.metadata.size.bytes;
+        let deleted = item.metadata.deleted;
+        if stats.oldest.is_none_or(|v| deleted.datetime < v.datetime) {
+            stats.oldest = Some(deleted);
+        }
+    }
+    res.sort_by(|a, b| a.volume.path.cmp(&b.volume.path));
+    res
+}
+
+fn volume_of(path: &Path, mounts: &[PathBuf]) -> PathBuf {
+    mounts
+        .iter()
+        .filter(|v| path.starts_with(v))
+        .max_by_key(|v| v.as_os_str().len())
+        .cloned()
+        .unwrap_or_else(|| path.ancestors().last().unwrap_or(path).to_path_buf())
+}
+
+/// The items of `items` to purge at `now` (UTC), oldest first: those older than the age limit, then as many more of
+/// the oldest as it takes to fit the rest in the size budget.
+pub(crate) fn expired<'a>(
+    items: &'a [PitouTrashItem],
+    retention: &TrashRetention,
+    now: NaiveDateTime,
+) -> Vec<&'a PitouTrashItem> {
+    let mut items = items.iter().collect::<Vec<_>>();
+    items.sort_by_key(|v| v.metadata.deleted.datetime);
+    let mut kept = items.iter().map(|v| v.metadata.size.bytes).sum::<u64>();
+    let mut res = Vec::new();
+    for item in items {
+        let too_old = retention.max_age_days.is_some_and(|days| {
+            now - item.metadata.deleted.datetime > chrono::Duration::days(days as i64)
+        });
+        let over_budget = retention.max_size.is_some_and(|size| kept > size);
+        if !too_old && !over_budget {
+            break;
+        }
+        kept -= item.metadata.size.bytes;
+        res.push(item);
+    }
+    res
+}
+
+#[cfg(test)]
+mod test_mod {
+    use super::*;
+    use crate::{PitouDateTime, PitouTrashItemMetadata};
+
+    fn item(path: &str, days_ago: i64, size: u64, now: NaiveDateTime) -> PitouTrashItem {
+        PitouTrashItem {
+            original_path: PitouFilePath::from_pathbuf(PathBuf::from(path)),
+            metadata: PitouTrashItemMetadata {
+                id: path.to_owned(),
+                deleted: PitouDateTime {
+                    datetime: now - chrono::Duration::days(days_ago),
+                },
+                size: PitouFileSize::new(size),
+                is_dir: false,
+            },
+        }
+    }
+
+    #[test]
+    fn test_retention() {
+        let now = chrono::Utc::now().naive_utc();
+        let items = [
+            item("/home/a", 40, 10, now),
+            item("/media/usb/b", 20, 30, now),
+            item("/home/c", 10, 50, now),
+            item("/home/d", 1, 5, now),
+        ];
+        let ids = |v: Vec<&PitouTrashItem>| v.iter().map(|u| u.id().to_owned()).collect::<Vec<_>>();
+
+        let by_age = TrashRetention {
+            max_age_days: Some(30),
+            max_size: None,
+        };
+        assert_eq!(ids(expired(&items, &by_age, now)), ["/home/a"]);
+        let by_size = TrashRetention {
+            max_age_days: None,
+            max_size: Some(60),
+        };
+        assert_eq!(
+            ids(expired(&items, &by_size, now)),
+            ["/home/a", "/media/usb/b"]
+        );
+        assert!(expired(&items, &TrashRetention::default(), now).is_empty());
+
+        let mounts = [PathBuf::from("/"), PathBuf::from("/media/usb")];
+        let stats = stats(&items, &mounts);
+        assert_eq!(stats.len(), 2);
+        assert!(stats[0].volume.path == Path::new("/"));
+        assert_eq!((stats[0].items, stats[0].size.bytes), (3, 65));
+        assert_eq!((stats[1].items, stats[1].size.bytes), (1, 30));
+        assert!(stats[0].oldest.unwrap().datetime == items[0].metadata.deleted.datetime);
+    }
+}
@@ -0,0 +1,128 @@
+//! The system trash, reached through the `trash` crate.
+
+use std::{io, path::PathBuf, str::FromStr};
+
+use chrono::DateTime;
+use trash::TrashItem;
+
+use crate::{
+    msg::{RestoreConflict, RestoreOptions},
+    PitouDateTime, PitouFilePath, PitouFileSize, PitouTrashItem, PitouTrashItemMetadata,
+};
+
+fn to_io_error(e: trash::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+/// The `trash` crate reports no progress.
+pub(super) fn delete(path: &std::path::Path, _: &mut dyn FnMut(u64)) -> io::Result<()> {
+    trash::delete(path).map_err(to_io_error)
+}
+
+pub(super) fn list() -> io::Result<Vec<PitouTrashItem>> {
+    trash::os_limited::list()
+        .map(|v| v.into_iter().filter_map(|u| u.try_into().ok()).collect())
+        .map_err(to_io_error)
+}
+
+/// The system trash only restores items to where they were deleted from, so restoring elsewhere or under another name
+/// is refused.
+pub(super) fn restore(
+    item: &PitouTrashItem,
+    options: &RestoreOptions,
+) -> io::Result<Option<PathBuf>> {
+    let unsupported = |what| io::Error::new(io::ErrorKind::Unsupported, what);
+    if options.destination.is_some() {
+        return Err(unsupported(
+            "the system trash cannot restore to another folder",
+        ));
+    }
+    let target = item.original_path.path.clone();
+    if std::fs::symlink_metadata(&target).is_ok() {
+        match options.conflict {
+            RestoreConflict::Skip => return Ok(None),
+            RestoreConflict::Overwrite => delete(&target)?,
+            RestoreConflict::Rename => {
+                return Err(unsupported(
+                    "the system trash cannot restore under another name",
+                ))
+            }
+        }
+    }
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    trash::os_limited::restore_all(Some(TrashItem::from(item))).map_err(to_io_error)?;
+    Ok(Some(target))
+}
+
+/// The `trash` crate keeps the storage of the system trash to itself.
+pub(super) fn location(_: &PitouTrashItem) -> io::Result<PathBuf> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the system trash does not expose where items are kept",
+    ))
+}
+
+pub(super) fn purge(item: &PitouTrashItem) -> io::Result<()> {
+    trash::os_limited::purge_all(Some(TrashItem::from(item))).map_err(to_io_error)
+}
+
+impl From<&PitouTrashItem> for TrashItem {
+    fn from(
+        PitouTrashItem {
+            original_path,
+            metadata: PitouTrashItemMetadata { id, deleted, .. },
+        }: &PitouTrashItem,
+    ) -> Self {
+        let time_deleted = deleted.datetime.and_utc().timestamp();
+        let name = original_path.name().to_owned();
+        let original_parent = original_path
+            .path
+            .parent()
+            .unwrap_or(&original_path.path)
+            .to_path_buf();
+
+        Self {
+            id: std::ffi::OsString::from_str(id).unwrap(),
+            name,
+            time_deleted,
+            original_parent,
+        }
+    }
+}
+
+impl TryFrom<TrashItem> for PitouTrashItem {
+    type Error = trash::Error;
+    fn try_from(item: TrashItem) -> Result<Self, Self::Error> {
+        let (size, is_dir) = match trash::os_limited::metadata(&item)?.size {
+            trash::TrashItemSize::Bytes(val) => (val, false),
+            trash::TrashItemSize::Entries(val) => (val as u64, true),
+        };
+
+        let TrashItem {
+            id,
+            name,
+            mut original_parent,
+            time_deleted,
+        } = item;
+
+        original_parent.push(name);
+
+        let metadata = PitouTrashItemMetadata {
+            id: id.into_string().unwrap(),
+            deleted: PitouDateTime {
+                datetime: DateTime::from_timestamp_millis(1000 * time_deleted)
+                    .unwrap()
+                    .naive_utc(),
+            },
+            is_dir,
+            size: PitouFileSize::new(size),
+        };
+
+        Ok(PitouTrashItem {
+            original_path: PitouFilePath::from_pathbuf(original_parent),
+            metadata,
+        })
+    }
+}
@@ -0,0 +1,320 @@
+//! Disk usage scans. Each scan walks a folder on a thread of its own and is followed through an id, like transfers.
+//!
+//! A scan stays on the filesystem it starts on and counts every hardlinked file once, so that its total matches the
+//! space the files take.
+
+use std::{
+    cmp::Reverse,
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Instant,
+};
+
+use crate::{
+    collections::Registry,
+    msg::{ScanState, TransferSessionID, UsageMsg, UsageNode, UsageOptions},
+    PitouDrive, PitouFilePath,
+};
+
+#[derive(Default)]
+struct Counters {
+    bytes: u64,
+    files: u64,
+    dirs: u64,
+    errors: u64,
+}
+
+struct UsageConfig {
+    id: TransferSessionID,
+    started: Instant,
+    expected_bytes: Option<u64>,
+    counters: Mutex<Counters>,
+    cancelled: AtomicBool,
+    state: Mutex<ScanState>,
+    tree: Mutex<Option<UsageNode>>,
+}
+
+impl UsageConfig {
+    fn is_ongoing(&self) -> bool {
+        *self.state.lock().unwrap() == ScanState::Scanning
+    }
+
+    fn read(&self) -> UsageMsg {
+        let counters = self.counters.lock().unwrap();
+        UsageMsg {
+            id: self.id,
+            state: *self.state.lock().unwrap(),
+            time_elapsed: self.started.elapsed(),
+            bytes: counters.bytes,
+            files: counters.files,
+            dirs: counters.dirs,
+            expected_bytes: self.expected_bytes,
+            errors: counters.errors,
+            tree: self.tree.lock().unwrap().clone(),
+        }
+    }
+}
+
+type UsageSessions = Mutex<Registry<Arc<UsageConfig>>>;
+static USAGE_SESSIONS: OnceLock<UsageSessions> = OnceLock::new();
+
+fn get_sessions() -> &'static UsageSessions {
+    USAGE_SESSIONS.get_or_init(|| Mutex::new(Registry::new()))
+}
+
+fn add_new_session(expected_bytes: Option<u64>) -> Arc<UsageConfig> {
+    let mut sessions = get_sessions().lock().unwrap();
+    let key = sessions.insert_with(|key| {
+        Arc::new(UsageConfig {
+            id: key.into(),
+            started: Instant::now(),
+            expected_bytes,
+            counters: Mutex::new(Counters::default()),
+            cancelled: AtomicBool::new(false),
+            state: Mutex::new(ScanState::Scanning),
+            tree: Mutex::new(None),
+        })
+    });
+    sessions.get(key).unwrap().clone()
+}
+
+/// Starts measuring what takes up the space under `path`.
+pub fn scan_path(path: PitouFilePath, options: UsageOptions) -> TransferSessionID {
+    begin(path.path, None, options)
+}
+
+/// Starts measuring what takes up the space of `drive`.
+pub fn scan_drive(drive: &PitouDrive, options: UsageOptions) -> TransferSessionID {
+    let used = drive.total_space.saturating_sub(drive.free_space);
+    begin(drive.mount_point.path.clone(), Some(used), options)
+}
+
+fn begin(root: PathBuf, expected_bytes: Option<u64>, options: UsageOptions) -> TransferSessionID {
+    let config = add_new_session(expected_bytes);
+    let id = config.id;
+    std::thread::spawn(move || {
+        let device = device_of(&root);
+        let mut walk = Walk {
+            config: &config,
+            options,
+            device,
+            seen: HashSet::new(),
+        };
+        let tree = walk.visit(&root, 0);
+        let mut state = config.state.lock().unwrap();
+        if config.cancelled.load(Ordering::Relaxed) {
+            *state = ScanState::Cancelled
+        } else {
+            *config.tree.lock().unwrap() = tree;
+            *state = ScanState::Completed
+        }
+    });
+    id
+}
+
+struct Walk<'a> {
+    config: &'a UsageConfig,
+    options: UsageOptions,
+    /// the filesystem the scan started on
+    device: Option<u64>,
+    /// hardlinked files already counted
+    seen: HashSet<(u64, u64)>,
+}
+
+impl Walk<'_> {
+    /// Measures `path` and whatever it holds. `None` for items left out: hardlinks seen before, other filesystems and
+    /// anything reached after the scan was cancelled.
+    fn visit(&mut self, path: &Path, depth: u32) -> Option<UsageNode> {
+        if self.config.cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
+        let name = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned();
+        let metadata = match std::fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                self.config.counters.lock().unwrap().errors += 1;
+                return None;
+            }
+        };
+        if device_of_metadata(&metadata) != self.device {
+            return None;
+        }
+
+        if !metadata.is_dir() {
+            if let Some(id) = hardlink_id(&metadata) {
+                if !self.seen.insert(id) {
+                    return None;
+                }
+            }
+            let bytes = disk_usage(&metadata);
+            let mut counters = self.config.counters.lock().unwrap();
+            counters.bytes += bytes;
+            counters.files += 1;
+            return Some(UsageNode {
+                name,
+                bytes,
+                files: 1,
+                ..Default::default()
+            });
+        }
+
+        let mut node = UsageNode {
+            name,
+            is_dir: true,
+            bytes: disk_usage(&metadata),
+            ..Default::default()
+        };
+        {
+            let mut counters = self.config.counters.lock().unwrap();
+            counters.bytes += node.bytes;
+            counters.dirs += 1;
+        }
+        let Ok(rd) = std::fs::read_dir(path) else {
+            self.config.counters.lock().unwrap().errors += 1;
+            return Some(node);
+        };
+        let mut children = Vec::new();
+        for entry in rd.flatten() {
+            let Some(child) = self.visit(&entry.path(), depth + 1) else {
+                continue;
+            };
+            node.bytes += child.bytes;
+            node.files += child.files;
+            node.dirs += child.dirs + child.is_dir as u64;
+            children.push(child);
+        }
+        if depth < self.options.max_depth {
+            children.sort_unstable_by_key(|v| Reverse(v.bytes));
+            let rest = children.split_off(children.len().min(self.options.max_children as usize));
+            node.other_bytes = rest.iter().map(|v| v.bytes).sum();
+            node.other_items = rest.len() as u64;
+            node.children = children;
+        } else {
+            node.other_bytes = children.iter().map(|v| v.bytes).sum();
+            node.other_items = children.len() as u64;
+        }
+        Some(node)
+    }
+}
+
+#[cfg(unix)]
+fn device_of_metadata(metadata: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device_of_metadata(_: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+fn device_of(path: &Path) -> Option<u64> {
+    std::fs::symlink_metadata(path)
+        .ok()
+        .and_then(|v| device_of_metadata(&v))
+}
+
+#[cfg(unix)]
+pub(crate) fn hardlink_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn hardlink_id(_: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(unix)]
+fn disk_usage(metadata: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    // st_blocks is in 512 byte units whatever the block size of the filesystem
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn disk_usage(metadata: &std::fs::Metadata) -> u64 {
+    metadata.len()
+}
+
+/// Stops the scan with `id`. Returns false if there is no such scan or it is already over.
+pub fn cancel_scan(id: TransferSessionID) -> bool {
+    match get_sessions().lock().unwrap().get(id.into()) {
+        Some(config) if config.is_ongoing() => {
+            config.cancelled.store(true, Ordering::Relaxed);
+            true
+        }
+        _ => false,
+    }
+}
+
+pub fn get_all_active_sessions() -> Vec<UsageMsg> {
+    get_sessions()
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(_, v)| if v.is_ongoing() { Some(v.read()) } else { None })
+        .collect()
+}
+
+pub fn get_session_with_id(id: TransferSessionID) -> Option<UsageMsg> {
+    let config = get_sessions().lock().unwrap().get(id.into()).cloned();
+    config.map(|v| v.read())
+}
+
+/// Forgets finished scans, along with their trees.
+pub fn clean_dead_sessions() {
+    get_sessions().lock().unwrap().retain(|_, v| v.is_ongoing());
+}
+
+#[cfg(test)]
+mod test_mod {
+    use super::*;
+    use crate::backend::testing;
+
+    fn wait_for(id: TransferSessionID) -> UsageMsg {
+        testing::wait_for(
+            || get_session_with_id(id).unwrap(),
+            |msg| msg.state != ScanState::Scanning,
+        )
+    }
+
+    #[test]
+    fn test_usage_tree() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let videos = root.join("videos").join("2024");
+        std::fs::create_dir_all(&videos).unwrap();
+        std::fs::create_dir_all(root.join("notes")).unwrap();
+        std::fs::write(videos.join("trip.mp4"), vec![1; 64 * 1024]).unwrap();
+        std::fs::write(root.join("notes").join("a.txt"), [2; 10]).unwrap();
+        std::fs::hard_link(videos.join("trip.mp4"), root.join("notes").join("trip.mp4")).unwrap();
+
+        let options = UsageOptions {
+            max_depth: 1,
+            max_children: 1,
+        };
+        let path = PitouFilePath::from_pathbuf(root.to_path_buf());
+        let msg = wait_for(scan_path(path, options));
+        assert_eq!(msg.state, ScanState::Completed);
+        let tree = msg.tree.unwrap();
+
+        // the hardlink is counted once, under whichever name was reached first
+        assert_eq!((tree.files, tree.dirs), (2, 3));
+        assert_eq!(tree.bytes, msg.bytes);
+        assert_eq!(tree.children.len(), 1);
+        assert_eq!(tree.other_items, 1);
+        let largest = &tree.children[0];
+        assert!(largest.bytes >= 64 * 1024 && largest.bytes + tree.other_bytes <= tree.bytes);
+        // below the depth limit folders are only added up
+        assert!(largest.children.is_empty() && largest.other_items >= 1);
+    }
+}
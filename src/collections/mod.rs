@@ -1 +1,3 @@
+mod registry;
 
+pub use registry::{Registry, RegistryKey};
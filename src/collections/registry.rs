@@ -0,0 +1,145 @@
+/// Identifies a value in a [`Registry`]. A key stays valid until its value is removed and is never reused afterwards:
+/// the slot it points to may be filled again, but under a newer generation.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct RegistryKey {
+    pub idx: usize,
+    pub generation: u64,
+}
+
+struct Slot<T> {
+    generation: u64,
+    value: Option<T>,
+}
+
+/// A slot map handing out generational keys. Removing values never moves the others, so keys keep their meaning.
+pub struct Registry<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<usize>,
+    len: usize,
+}
+
+impl<T> Default for Registry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Registry<T> {
+    pub const fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Stores the value built by `make`, which receives the key it will be stored under.
+    pub fn insert_with(&mut self, make: impl FnOnce(RegistryKey) -> T) -> RegistryKey {
+        let idx = match self.free.pop() {
+            Some(idx) => idx,
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: None,
+                });
+                self.slots.len() - 1
+            }
+        };
+        let slot = &mut self.slots[idx];
+        // generations start at 1 so that a zeroed key never matches
+        slot.generation += 1;
+        let key = RegistryKey {
+            idx,
+            generation: slot.generation,
+        };
+        slot.value = Some(make(key));
+        self.len += 1;
+        key
+    }
+
+    pub fn insert(&mut self, value: T) -> RegistryKey {
+        self.insert_with(|_| value)
+    }
+
+    pub fn get(&self, key: RegistryKey) -> Option<&T> {
+        self.slots
+            .get(key.idx)
+            .filter(|slot| slot.generation == key.generation)
+            .and_then(|slot| slot.value.as_ref())
+    }
+
+    pub fn get_mut(&mut self, key: RegistryKey) -> Option<&mut T> {
+        self.slots
+            .get_mut(key.idx)
+            .filter(|slot| slot.generation == key.generation)
+            .and_then(|slot| slot.value.as_mut())
+    }
+
+    pub fn remove(&mut self, key: RegistryKey) -> Option<T> {
+        let slot = self
+            .slots
+            .get_mut(key.idx)
+            .filter(|slot| slot.generation == key.generation)?;
+        let value = slot.value.take()?;
+        self.free.push(key.idx);
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// Removes every value for which `keep` returns false.
+    pub fn retain(&mut self, mut keep: impl FnMut(RegistryKey, &T) -> bool) {
+        for (idx, slot) in self.slots.iter_mut().enumerate() {
+            let key = RegistryKey {
+                idx,
+                generation: slot.generation,
+            };
+            if matches!(&slot.value, Some(value) if !keep(key, value)) {
+                slot.value = None;
+                self.free.push(idx);
+                self.len -= 1;
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (RegistryKey, &T)> {
+        self.slots.iter().enumerate().filter_map(|(idx, slot)| {
+            let key = RegistryKey {
+                idx,
+                generation: slot.generation,
+            };
+            slot.value.as_ref().map(|value| (key, value))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_mod {
+    use super::*;
+
+    #[test]
+    fn test_stale_keys() {
+        let mut registry = Registry::new();
+        let first = registry.insert("first");
+        let second = registry.insert("second");
+        assert_eq!(registry.remove(first), Some("first"));
+        assert_eq!(registry.get(first), None);
+
+        let third = registry.insert("third");
+        assert_eq!(third.idx, first.idx);
+        assert_eq!(registry.get(first), None);
+        assert_eq!(registry.get(third), Some(&"third"));
+
+        registry.retain(|_, v| *v != "second");
+        assert_eq!(registry.get(second), None);
+        assert_eq!(registry.len(), 1);
+        assert_eq!(registry.iter().map(|(k, _)| k).collect::<Vec<_>>(), [third]);
+    }
+}
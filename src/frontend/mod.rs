@@ -8,8 +8,9 @@ use std::{
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    AppMenu, AppSettings, ColorTheme, FrontendSearchOptions, GeneralFolder, ItemsView, PitouDrive,
-    PitouFile, PitouFileFilter, PitouFileSort, PitouTrashItem,
+    AppMenu, AppSettings, ColorTheme, DriveEvent, DriveEventKind, FrontendSearchOptions,
+    GeneralFolder, ItemsView, PitouDrive, PitouDriveFilter, PitouFile, PitouFileFilter,
+    PitouFileSort, PitouTrashItem,
 };
 
 use self::extra::FolderTracker;
@@ -505,6 +506,26 @@ impl StaticData {
         *self.drives.borrow_mut() = Some(drives);
     }
 
+    /// Brings the drive list up to date with `events` from the drive monitor, keeping to the drives `filter` lets in.
+    /// Removed drives are also unselected.
+    pub fn apply_drive_events(&self, events: Vec<DriveEvent>, filter: PitouDriveFilter) {
+        let mut drives = match &*self.drives.borrow() {
+            Some(drives) => drives.iter().cloned().collect::<Vec<_>>(),
+            None => return,
+        };
+        for DriveEvent { kind, drive } in events {
+            drives.retain(|v| **v != drive);
+            let drive = Rc::new(drive);
+            if kind == DriveEventKind::Removed || !filter.includes(&drive) {
+                self.clear_drive_selection(drive)
+            } else {
+                drives.push(drive)
+            }
+        }
+        drives.sort_unstable_by(|a, b| a.mount_point.name().cmp(b.mount_point.name()));
+        self.update_drives(Rc::new(drives));
+    }
+
     pub fn clear_dir_entry_selection(&self, item: Rc<PitouFile>) {
         if let Selections::FolderEntries(en) = &mut *self.selections.borrow_mut() {
             en.items.remove(&FolderEntry { item });
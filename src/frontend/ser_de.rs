@@ -1,4 +1,4 @@
-use std::{path::PathBuf, rc::Rc};
+use std::{path::PathBuf, rc::Rc, time::Duration};
 
 use serde::{
     de::{SeqAccess, Visitor},
@@ -6,9 +6,16 @@ use serde::{
 };
 
 use crate::{
-    msg::SearchMsg, search::SimplifiedSearchOptions, GeneralFolder, PitouDrive, PitouDriveKind,
-    PitouFile, PitouFileFilter, PitouFileMetadata, PitouFilePath, PitouTrashItem,
-    PitouTrashItemMetadata,
+    msg::{
+        ArchiveMsg, CompareEntry, CompareStatus, DeleteMsg, DuplicateGroup, DuplicateMsg,
+        FinishedTransfer, RestoreConflict, RestoreOptions, RestoreOutcome, RestoreResult,
+        ScanState, SearchMsg, SyncStarted, TransferError, TransferMsg, TransferOutcome,
+        TransferPlan, TransferProgress, TransferSessionID, TransferState,
+    },
+    search::SimplifiedSearchOptions,
+    DriveEvent, DriveEventKind, GeneralFolder, PitouDateTime, PitouDrive, PitouDriveFileSystem,
+    PitouDriveKind, PitouFile, PitouFileFilter, PitouFileMetadata, PitouFilePath, PitouFileSize,
+    PitouTrashItem, PitouTrashItemMetadata, TrashQuery, TrashSort, TrashVolumeStats,
 };
 
 use super::extra::DirChildren;
@@ -79,6 +86,7 @@ impl<'d> Deserialize<'d> for PitouDrive {
             free_space: u64,
             is_removable: bool,
             kind: PitouDriveKind,
+            filesystem: PitouDriveFileSystem,
         }
 
         let PitouDrive {
@@ -88,6 +96,7 @@ impl<'d> Deserialize<'d> for PitouDrive {
             free_space,
             is_removable,
             kind,
+            filesystem,
         } = PitouDrive::deserialize(dz)?;
 
         Ok(Self {
@@ -97,6 +106,7 @@ impl<'d> Deserialize<'d> for PitouDrive {
             free_space,
             is_removable,
             kind,
+            filesystem,
         })
     }
 }
@@ -219,3 +229,456 @@ impl<'d> Deserialize<'d> for SearchMsg {
         Ok(real_msg)
     }
 }
+
+impl<'d> Deserialize<'d> for TransferMsg {
+    fn deserialize<D: Deserializer<'d>>(dz: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        enum TransferMsg {
+            Copy {
+                id: TransferSessionID,
+                state: TransferState,
+                time_elapsed: Duration,
+                progress: TransferProgress,
+                mismatches: Vec<PitouFilePath>,
+                bandwidth_limit: Option<u64>,
+            },
+            Move {
+                id: TransferSessionID,
+                state: TransferState,
+                time_elapsed: Duration,
+                progress: TransferProgress,
+                mismatches: Vec<PitouFilePath>,
+                bandwidth_limit: Option<u64>,
+            },
+        }
+
+        let res = match TransferMsg::deserialize(dz)? {
+            TransferMsg::Copy {
+                id,
+                state,
+                time_elapsed,
+                progress,
+                mismatches,
+                bandwidth_limit,
+            } => Self::Copy {
+                id,
+                state,
+                time_elapsed,
+                progress,
+                mismatches,
+                bandwidth_limit,
+            },
+            TransferMsg::Move {
+                id,
+                state,
+                time_elapsed,
+                progress,
+                mismatches,
+                bandwidth_limit,
+            } => Self::Move {
+                id,
+                state,
+                time_elapsed,
+                progress,
+                mismatches,
+                bandwidth_limit,
+            },
+        };
+        Ok(res)
+    }
+}
+
+impl<'d> Deserialize<'d> for TransferPlan {
+    fn deserialize<D: Deserializer<'d>>(dz: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct TransferPlan {
+            copy: bool,
+            files: u64,
+            dirs: u64,
+            total_bytes: u64,
+            conflicts: Vec<PitouFilePath>,
+            permission_problems: Vec<PitouFilePath>,
+            free_space: Option<u64>,
+            required_space: u64,
+            same_device: bool,
+        }
+
+        let TransferPlan {
+            copy,
+            files,
+            dirs,
+            total_bytes,
+            conflicts,
+            permission_problems,
+            free_space,
+            required_space,
+            same_device,
+        } = TransferPlan::deserialize(dz)?;
+
+        Ok(Self {
+            copy,
+            files,
+            dirs,
+            total_bytes,
+            conflicts,
+            permission_problems,
+            free_space,
+            required_space,
+            same_device,
+        })
+    }
+}
+
+impl<'d> Deserialize<'d> for TransferError {
+    fn deserialize<D: Deserializer<'d>>(dz: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct TransferError {
+            path: PitouFilePath,
+            message: String,
+        }
+
+        let TransferError { path, message } = TransferError::deserialize(dz)?;
+        Ok(Self { path, message })
+    }
+}
+
+impl<'d> Deserialize<'d> for FinishedTransfer {
+    fn deserialize<D: Deserializer<'d>>(dz: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct FinishedTransfer {
+            id: TransferSessionID,
+            copy: bool,
+            outcome: TransferOutcome,
+            duration: Duration,
+            bytes: u64,
+            files: u64,
+            errors: Vec<TransferError>,
+        }
+
+        let FinishedTransfer {
+            id,
+            copy,
+            outcome,
+            duration,
+            bytes,
+            files,
+            errors,
+        } = FinishedTransfer::deserialize(dz)?;
+
+        Ok(Self {
+            id,
+            copy,
+            outcome,
+            duration,
+            bytes,
+            files,
+            errors,
+        })
+    }
+}
+
+impl<'d> Deserialize<'d> for DeleteMsg {
+    fn deserialize<D: Deserializer<'d>>(dz: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct DeleteMsg {
+            id: TransferSessionID,
+            state: TransferState,
+            time_elapsed: Duration,
+            items_done: u64,
+            items_total: u64,
+            failures: Vec<TransferError>,
+            outcome: Option<TransferOutcome>,
+        }
+
+        let DeleteMsg {
+            id,
+            state,
+            time_elapsed,
+            items_done,
+            items_total,
+            failures,
+            outcome,
+        } = DeleteMsg::deserialize(dz)?;
+
+        Ok(Self {
+            id,
+            state,
+            time_elapsed,
+            items_done,
+            items_total,
+            failures,
+            outcome,
+        })
+    }
+}
+
+impl Serialize for RestoreOptions {
+    fn serialize<S: Serializer>(&self, sz: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct RestoreOptions<'a> {
+            conflict: RestoreConflict,
+            destination: Option<&'a PitouFilePath>,
+        }
+
+        RestoreOptions {
+            conflict: self.conflict,
+            destination: self.destination.as_ref(),
+        }
+        .serialize(sz)
+    }
+}
+
+impl<'d> Deserialize<'d> for RestoreResult {
+    fn deserialize<D: Deserializer<'d>>(dz: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct RestoreResult {
+            id: String,
+            outcome: RestoreOutcome,
+            restored_to: Option<PitouFilePath>,
+        }
+
+        let RestoreResult {
+            id,
+            outcome,
+            restored_to,
+        } = RestoreResult::deserialize(dz)?;
+        Ok(Self {
+            id,
+            outcome,
+            restored_to,
+        })
+    }
+}
+
+impl<'d> Deserialize<'d> for TrashVolumeStats {
+    fn deserialize<D: Deserializer<'d>>(dz: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct TrashVolumeStats {
+            volume: PitouFilePath,
+            items: u64,
+            size: PitouFileSize,
+            oldest: Option<PitouDateTime>,
+        }
+
+        let TrashVolumeStats {
+            volume,
+            items,
+            size,
+            oldest,
+        } = TrashVolumeStats::deserialize(dz)?;
+        Ok(Self {
+            volume,
+            items,
+            size,
+            oldest,
+        })
+    }
+}
+
+impl Serialize for TrashQuery {
+    fn serialize<S: Serializer>(&self, sz: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct TrashQuery<'a> {
+            input: &'a str,
+            search_kind: u8,
+            case_sensitive: bool,
+            original_dir: Option<&'a PitouFilePath>,
+            deleted_after: Option<PitouDateTime>,
+            deleted_before: Option<PitouDateTime>,
+            min_size: Option<u64>,
+            max_size: Option<u64>,
+            sort: Option<TrashSort>,
+        }
+
+        TrashQuery {
+            input: &self.input,
+            search_kind: self.search_kind,
+            case_sensitive: self.case_sensitive,
+            original_dir: self.original_dir.as_ref(),
+            deleted_after: self.deleted_after,
+            deleted_before: self.deleted_before,
+            min_size: self.min_size,
+            max_size: self.max_size,
+            sort: self.sort,
+        }
+        .serialize(sz)
+    }
+}
+
+impl<'d> Deserialize<'d> for DriveEvent {
+    fn deserialize<D: Deserializer<'d>>(dz: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct DriveEvent {
+            kind: DriveEventKind,
+            drive: PitouDrive,
+        }
+
+        let DriveEvent { kind, drive } = DriveEvent::deserialize(dz)?;
+        Ok(Self { kind, drive })
+    }
+}
+
+impl<'d> Deserialize<'d> for DuplicateGroup {
+    fn deserialize<D: Deserializer<'d>>(dz: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct DuplicateGroup {
+            size: u64,
+            files: Vec<PitouFile>,
+        }
+
+        let DuplicateGroup { size, files } = DuplicateGroup::deserialize(dz)?;
+        Ok(Self { size, files })
+    }
+}
+
+impl<'d> Deserialize<'d> for DuplicateMsg {
+    fn deserialize<D: Deserializer<'d>>(dz: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct DuplicateMsg {
+            id: TransferSessionID,
+            state: ScanState,
+            time_elapsed: Duration,
+            files: u64,
+            bytes_hashed: u64,
+            groups_found: u64,
+            wasted_bytes: u64,
+            errors: u64,
+            groups: Vec<DuplicateGroup>,
+        }
+
+        let DuplicateMsg {
+            id,
+            state,
+            time_elapsed,
+            files,
+            bytes_hashed,
+            groups_found,
+            wasted_bytes,
+            errors,
+            groups,
+        } = DuplicateMsg::deserialize(dz)?;
+
+        Ok(Self {
+            id,
+            state,
+            time_elapsed,
+            files,
+            bytes_hashed,
+            groups_found,
+            wasted_bytes,
+            errors,
+            groups,
+        })
+    }
+}
+
+impl<'d> Deserialize<'d> for CompareEntry {
+    fn deserialize<D: Deserializer<'d>>(dz: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct CompareEntry {
+            status: CompareStatus,
+            left: Option<PitouFile>,
+            right: Option<PitouFile>,
+        }
+
+        let CompareEntry {
+            status,
+            left,
+            right,
+        } = CompareEntry::deserialize(dz)?;
+        Ok(Self {
+            status,
+            left,
+            right,
+        })
+    }
+}
+
+impl<'d> Deserialize<'d> for SyncStarted {
+    fn deserialize<D: Deserializer<'d>>(dz: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct SyncStarted {
+            transfer: Option<TransferSessionID>,
+            deletion: Option<TransferSessionID>,
+            conflicts: Vec<CompareEntry>,
+        }
+
+        let SyncStarted {
+            transfer,
+            deletion,
+            conflicts,
+        } = SyncStarted::deserialize(dz)?;
+        Ok(Self {
+            transfer,
+            deletion,
+            conflicts,
+        })
+    }
+}
+
+impl<'d> Deserialize<'d> for ArchiveMsg {
+    fn deserialize<D: Deserializer<'d>>(dz: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        enum ArchiveMsg {
+            Compress {
+                id: TransferSessionID,
+                state: TransferState,
+                time_elapsed: Duration,
+                progress: TransferProgress,
+                archive: PitouFilePath,
+                errors: Vec<TransferError>,
+                outcome: Option<TransferOutcome>,
+            },
+            Extract {
+                id: TransferSessionID,
+                state: TransferState,
+                time_elapsed: Duration,
+                progress: TransferProgress,
+                archive: PitouFilePath,
+                destination: PitouFilePath,
+                errors: Vec<TransferError>,
+                outcome: Option<TransferOutcome>,
+            },
+        }
+
+        let res = match ArchiveMsg::deserialize(dz)? {
+            ArchiveMsg::Compress {
+                id,
+                state,
+                time_elapsed,
+                progress,
+                archive,
+                errors,
+                outcome,
+            } => Self::Compress {
+                id,
+                state,
+                time_elapsed,
+                progress,
+                archive,
+                errors,
+                outcome,
+            },
+            ArchiveMsg::Extract {
+                id,
+                state,
+                time_elapsed,
+                progress,
+                archive,
+                destination,
+                errors,
+                outcome,
+            } => Self::Extract {
+                id,
+                state,
+                time_elapsed,
+                progress,
+                archive,
+                destination,
+                errors,
+                outcome,
+            },
+        };
+        Ok(res)
+    }
+}
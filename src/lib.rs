@@ -178,6 +178,49 @@ pub struct PitouDrive {
     pub free_space: u64,
     pub is_removable: bool,
     pub kind: PitouDriveKind,
+    pub filesystem: PitouDriveFileSystem,
+}
+
+/// What is known about the filesystem mounted on a drive. Fields the platform does not report are left empty.
+#[derive(Clone, Default, Serialize, Deserialize, PartialEq, Debug)]
+pub struct PitouDriveFileSystem {
+    /// such as `ext4`, `ntfs` or `tmpfs`
+    pub kind: String,
+    pub mount_options: Vec<String>,
+    pub is_read_only: bool,
+    /// the device or remote share the filesystem comes from
+    pub device: Option<String>,
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+    pub total_inodes: Option<u64>,
+    pub free_inodes: Option<u64>,
+    /// reached over the network, like nfs or smb shares
+    pub is_network: bool,
+    /// not backed by a storage device of its own, like tmpfs, overlay or fuse mounts
+    pub is_virtual: bool,
+}
+
+/// Which drives to list besides the ones on local storage devices.
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct PitouDriveFilter {
+    pub network: bool,
+    pub virtual_drives: bool,
+}
+
+impl Default for PitouDriveFilter {
+    fn default() -> Self {
+        Self {
+            network: true,
+            virtual_drives: false,
+        }
+    }
+}
+
+impl PitouDriveFilter {
+    pub fn includes(&self, drive: &PitouDrive) -> bool {
+        (self.network || !drive.filesystem.is_network)
+            && (self.virtual_drives || !drive.filesystem.is_virtual)
+    }
 }
 
 impl PitouDrive {
@@ -197,6 +240,35 @@ impl PartialEq for PitouDrive {
     }
 }
 
+impl Clone for PitouDrive {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            mount_point: PitouFilePath::from_pathbuf(self.mount_point.path.clone()),
+            total_space: self.total_space,
+            free_space: self.free_space,
+            is_removable: self.is_removable,
+            kind: self.kind,
+            filesystem: self.filesystem.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub enum DriveEventKind {
+    Added,
+    Removed,
+    /// the drive is still mounted but its name, size or kind changed
+    Changed,
+}
+
+/// A change in the set of mounted drives.
+pub struct DriveEvent {
+    pub kind: DriveEventKind,
+    /// the drive as it is now, or as it was last seen if it was removed
+    pub drive: PitouDrive,
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub enum PitouDriveKind {
     HDD,
@@ -324,6 +396,24 @@ pub struct PitouTrashItemMetadata {
     pub is_dir: bool,
 }
 
+/// What the trash holds on one volume.
+pub struct TrashVolumeStats {
+    /// mount point of the volume
+    pub volume: PitouFilePath,
+    pub items: u64,
+    pub size: PitouFileSize,
+    pub oldest: Option<PitouDateTime>,
+}
+
+/// What a purge of the trash removed.
+#[derive(Clone, Copy, Serialize, Deserialize, Default)]
+pub struct TrashPurgeSummary {
+    pub purged: u64,
+    pub bytes_freed: u64,
+    /// items that were due but could not be purged
+    pub failed: u64,
+}
+
 #[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum PitouFileSortOrder {
     Increasing,
@@ -378,6 +468,53 @@ impl PitouFileSort {
     }
 }
 
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TrashSort {
+    Name(PitouFileSortOrder),
+    DateDeleted(PitouFileSortOrder),
+    Size(PitouFileSortOrder),
+    OriginalPath(PitouFileSortOrder),
+}
+
+impl TrashSort {
+    pub fn sorted(self, mut items: Vec<PitouTrashItem>) -> Vec<PitouTrashItem> {
+        let order = match self {
+            TrashSort::Name(order)
+            | TrashSort::DateDeleted(order)
+            | TrashSort::Size(order)
+            | TrashSort::OriginalPath(order) => order,
+        };
+        match self {
+            TrashSort::Name(_) => items.sort_by(|a, b| a.name().cmp(b.name())),
+            TrashSort::DateDeleted(_) => items.sort_by_key(|v| v.metadata.deleted.datetime),
+            TrashSort::Size(_) => items.sort_by_key(|v| v.metadata.size.bytes),
+            TrashSort::OriginalPath(_) => {
+                items.sort_by(|a, b| a.original_path.path.cmp(&b.original_path.path))
+            }
+        }
+        if order == PitouFileSortOrder::Decreasing {
+            items.reverse()
+        }
+        items
+    }
+}
+
+/// Narrows down the items of the trash. Every condition that is set must hold.
+#[derive(Default)]
+pub struct TrashQuery {
+    /// matched against item names as `search_kind` says, like searches; empty matches every name
+    pub input: String,
+    pub search_kind: u8,
+    pub case_sensitive: bool,
+    /// only items deleted from this folder or from folders inside it
+    pub original_dir: Option<PitouFilePath>,
+    pub deleted_after: Option<PitouDateTime>,
+    pub deleted_before: Option<PitouDateTime>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub sort: Option<TrashSort>,
+}
+
 #[derive(Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub struct PitouFileFilter {
     pub files: bool,
@@ -406,17 +543,21 @@ impl PitouFileFilter {
     }
 
     pub fn map(self, file: PitouFile) -> Option<PitouFile> {
-        if (file.is_dir() && self.dirs)
-            || (file.is_file() && self.files)
-            || (file.is_link() && self.links)
-            || (file.is_sys_item() && self.sys_items)
-        {
+        if self.includes(&file) {
             Some(file)
         } else {
             None
         }
     }
 
+    /// Whether `file` is of a kind this filter lets through.
+    pub fn includes(&self, file: &PitouFile) -> bool {
+        (file.is_dir() && self.dirs)
+            || (file.is_file() && self.files)
+            || (file.is_link() && self.links)
+            || (file.is_sys_item() && self.sys_items)
+    }
+
     pub fn all_filtered(self) -> bool {
         !self.dirs && !self.files && !self.links
     }
@@ -542,6 +683,15 @@ impl GeneralFolder {
     }
 }
 
+/// Limits on what the trash keeps. Items past either limit are purged, oldest first.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+pub struct TrashRetention {
+    /// days an item is kept after being trashed
+    pub max_age_days: Option<u32>,
+    /// bytes the whole trash may hold
+    pub max_size: Option<u64>,
+}
+
 #[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum ItemsView {
     Grid,
@@ -559,6 +709,10 @@ pub struct AppSettings {
     pub show_parents: bool,
     pub items_sort: Option<PitouFileSort>,
     pub items_zoom: f32,
+    #[serde(default)]
+    pub trash_retention: TrashRetention,
+    #[serde(default)]
+    pub drive_filter: PitouDriveFilter,
 }
 
 impl AppSettings {
@@ -586,6 +740,8 @@ impl Default for AppSettings {
             show_parents: false,
             items_zoom: 1.0,
             items_sort: None,
+            trash_retention: TrashRetention::default(),
+            drive_filter: PitouDriveFilter::default(),
         }
     }
 }
@@ -596,6 +752,29 @@ pub struct DirChild {
     metadata: Option<PitouFileMetadata>,
 }
 
+impl DirChild {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn metadata(&self) -> &Option<PitouFileMetadata> {
+        &self.metadata
+    }
+}
+
+/// A look at the content of a trashed item, taken without restoring it.
+#[derive(Serialize, Deserialize)]
+pub enum TrashPreview {
+    /// the start of a text file
+    Text { head: String, truncated: bool },
+    /// a thumbnail of an image, encoded as `mime`
+    Image { mime: String, bytes: Vec<u8> },
+    /// the items directly inside a trashed folder
+    Directory(Vec<DirChild>),
+    /// content that cannot be previewed
+    Unavailable,
+}
+
 #[derive(PartialEq, Clone)]
 pub struct FrontendSearchOptions {
     pub input: String,
@@ -1,4 +1,4 @@
-use crate::PitouFile;
+use crate::{collections::RegistryKey, PitouFile, PitouFilePath};
 use serde::{Deserialize, Serialize};
 use std::{collections::LinkedList, time::Duration};
 
@@ -20,60 +20,643 @@ impl TransferState {
     }
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize)]
 pub enum TransferMsg {
     Copy {
         id: TransferSessionID,
         state: TransferState,
         time_elapsed: Duration,
+        progress: TransferProgress,
+        mismatches: Vec<PitouFilePath>,
+        bandwidth_limit: Option<u64>,
     },
     Move {
         id: TransferSessionID,
         state: TransferState,
         time_elapsed: Duration,
+        progress: TransferProgress,
+        mismatches: Vec<PitouFilePath>,
+        bandwidth_limit: Option<u64>,
     },
 }
 
-
 impl TransferMsg {
     pub fn details(self) -> (TransferState, Duration) {
         match self {
             TransferMsg::Copy {
-                id: _,
                 state,
                 time_elapsed,
+                ..
             } => (state, time_elapsed),
             TransferMsg::Move {
-                id: _,
                 state,
                 time_elapsed,
+                ..
             } => (state, time_elapsed),
         }
     }
 
     pub fn id(&self) -> TransferSessionID {
         match self {
-            Self::Copy { id, state: _, time_elapsed: _ } => *id,
-            Self::Move { id, state: _, time_elapsed: _ } => *id,
+            Self::Copy { id, .. } => *id,
+            Self::Move { id, .. } => *id,
         }
     }
 
     pub fn is_terminated(&self) -> bool {
         match self {
-            TransferMsg::Copy { id: _, state, time_elapsed: _ } => state.is_terminted(),
-            TransferMsg::Move { id: _, state, time_elapsed: _ } => state.is_terminted(),
+            TransferMsg::Copy { state, .. } => state.is_terminted(),
+            TransferMsg::Move { state, .. } => state.is_terminted(),
+        }
+    }
+
+    pub fn progress(&self) -> &TransferProgress {
+        match self {
+            TransferMsg::Copy { progress, .. } => progress,
+            TransferMsg::Move { progress, .. } => progress,
+        }
+    }
+
+    /// Source files whose copies failed verification. These copies were discarded.
+    pub fn mismatches(&self) -> &[PitouFilePath] {
+        match self {
+            TransferMsg::Copy { mismatches, .. } => mismatches,
+            TransferMsg::Move { mismatches, .. } => mismatches,
+        }
+    }
+
+    /// The bytes per second the session is currently held to, the tighter of its own and the global limit.
+    pub fn bandwidth_limit(&self) -> Option<u64> {
+        match self {
+            TransferMsg::Copy {
+                bandwidth_limit, ..
+            } => *bandwidth_limit,
+            TransferMsg::Move {
+                bandwidth_limit, ..
+            } => *bandwidth_limit,
         }
     }
 }
 
+/// Progress details of a transfer, computed by the backend so that every view reports the same numbers.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TransferProgress {
+    /// name of the file that most recently started copying
+    pub current_file: String,
+    pub files_done: u64,
+    pub files_total: u64,
+    /// bytes per second over the last few seconds
+    pub current_rate: u64,
+    /// bytes per second since the transfer started
+    pub average_rate: u64,
+    /// smoothed estimate of the time left, unknown until the transfer has been running for a moment
+    pub eta: Option<Duration>,
+    /// bytes that in-place block updates took from the existing destination instead of the source
+    pub saved_bytes: u64,
+}
+
 #[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct TransferSize {
     pub total: u64,
     pub current: u64,
 }
 
+/// Identifies a session for as long as the backend runs. `parity` is the generation of the registry slot at `idx`,
+/// so the id of a removed session never comes to refer to a newer one.
 #[derive(Clone, Copy, Serialize, Deserialize, Hash, Eq, PartialEq)]
 pub struct TransferSessionID {
     pub idx: i64,
     pub parity: i64,
 }
+
+impl From<RegistryKey> for TransferSessionID {
+    fn from(key: RegistryKey) -> Self {
+        Self {
+            idx: key.idx as i64,
+            parity: key.generation as i64,
+        }
+    }
+}
+
+impl From<TransferSessionID> for RegistryKey {
+    fn from(id: TransferSessionID) -> Self {
+        // negative values come from a bad id and must not match any slot
+        Self {
+            idx: usize::try_from(id.idx).unwrap_or(usize::MAX),
+            generation: u64::try_from(id.parity).unwrap_or(0),
+        }
+    }
+}
+
+/// Options that apply to every item of a paste session.
+#[derive(Clone, Copy, Serialize, Deserialize, Default)]
+pub struct TransferOptions {
+    pub preserve: PreserveMetadata,
+    pub links: LinkPolicy,
+    pub special_files: SpecialFilePolicy,
+    /// hash every copied file against its source before it takes its final name
+    pub verify: bool,
+    /// bytes per second the session starts out limited to; it can be changed while the session runs
+    pub bandwidth_limit: Option<u64>,
+    /// update files that already exist at the destination in place, rsync-style: blocks found in the destination
+    /// through a rolling checksum are kept or moved within it, even when data was inserted or removed before them,
+    /// and only the rest is written from the source
+    pub changed_blocks_only: bool,
+}
+
+/// Metadata carried over from the source items to their copies. Nothing is preserved by default.
+#[derive(Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct PreserveMetadata {
+    /// modification and access times
+    pub times: bool,
+    /// permission bits, or the read-only flag on Windows
+    pub permissions: bool,
+    /// extended attributes; ignored on platforms or destinations that do not support them
+    pub xattrs: bool,
+}
+
+impl PreserveMetadata {
+    pub const fn all() -> Self {
+        Self {
+            times: true,
+            permissions: true,
+            xattrs: true,
+        }
+    }
+}
+
+/// How transfers treat symbolic links.
+#[derive(Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub enum LinkPolicy {
+    /// copy what the link points to. Links that lead back to a folder being copied are skipped
+    #[default]
+    Follow,
+    /// recreate the link itself at the destination
+    CopyAsLink,
+    Skip,
+}
+
+/// How transfers treat FIFOs and device files. Sockets are always skipped.
+#[derive(Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub enum SpecialFilePolicy {
+    #[default]
+    Skip,
+    /// create a new FIFO or device node with the same type and device number. Device nodes usually need root
+    Recreate,
+}
+
+/// What pasting the clipboard into a folder would do, computed without touching anything.
+pub struct TransferPlan {
+    /// false if the clipboard items were cut rather than copied
+    pub copy: bool,
+    pub files: u64,
+    pub dirs: u64,
+    pub total_bytes: u64,
+    /// items that already exist in the destination folder
+    pub conflicts: Vec<PitouFilePath>,
+    /// items that cannot be read, or folders that cannot be written to
+    pub permission_problems: Vec<PitouFilePath>,
+    /// free space on the destination drive, if the drive could be found
+    pub free_space: Option<u64>,
+    /// bytes that have to be written on the destination drive, which cut items take up as well
+    pub required_space: u64,
+    /// whether every item lives on the same drive as the destination
+    pub same_device: bool,
+}
+
+impl TransferPlan {
+    pub fn insufficient_space(&self) -> bool {
+        matches!(self.free_space, Some(free) if free < self.required_space)
+    }
+
+    /// Whether the transfer can be expected to run to completion. Conflicts are left for the caller to decide on.
+    pub fn fits(&self) -> bool {
+        !self.insufficient_space() && self.permission_problems.is_empty()
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub enum TransferOutcome {
+    Completed,
+    /// some items were transferred and others failed
+    CompletedWithErrors,
+    /// none of the items were transferred
+    Failed,
+    /// stopped on request before it was over
+    Cancelled,
+}
+
+impl TransferOutcome {
+    /// The outcome of a session that met `errors` problems in total, with `failed` of its `total` items failing outright.
+    #[cfg(feature = "backend")]
+    pub(crate) fn judge(errors: usize, failed: usize, total: usize) -> Self {
+        if errors == 0 {
+            Self::Completed
+        } else if failed == total {
+            Self::Failed
+        } else {
+            Self::CompletedWithErrors
+        }
+    }
+}
+
+/// An item that could not be transferred.
+pub struct TransferError {
+    pub path: PitouFilePath,
+    pub message: String,
+}
+
+impl Clone for TransferError {
+    fn clone(&self) -> Self {
+        Self {
+            path: PitouFilePath::from_pathbuf(self.path.path.clone()),
+            message: self.message.clone(),
+        }
+    }
+}
+
+/// A summary kept for a while after a session has terminated.
+#[derive(Clone)]
+pub struct FinishedTransfer {
+    pub id: TransferSessionID,
+    pub copy: bool,
+    pub outcome: TransferOutcome,
+    pub duration: Duration,
+    pub bytes: u64,
+    pub files: u64,
+    pub errors: Vec<TransferError>,
+}
+
+/// The state of a delete session. Progress is measured in bytes, like transfers.
+pub struct DeleteMsg {
+    pub id: TransferSessionID,
+    pub state: TransferState,
+    pub time_elapsed: Duration,
+    pub items_done: u64,
+    pub items_total: u64,
+    /// items that could not be deleted
+    pub failures: Vec<TransferError>,
+    /// set once the session has terminated
+    pub outcome: Option<TransferOutcome>,
+}
+
+/// Proof that the user confirmed a permanent delete, issued for one exact set of items.
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct DeleteToken {
+    value: u64,
+}
+
+impl DeleteToken {
+    #[cfg(feature = "backend")]
+    pub(crate) fn new(value: u64) -> Self {
+        Self { value }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Default)]
+pub struct PermanentDeleteOptions {
+    /// times each file is overwritten with random data before it is unlinked; zero unlinks it right away
+    pub shred_passes: u32,
+}
+
+/// What to do when a restored trash item would land on an existing one.
+#[derive(Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, Debug)]
+pub enum RestoreConflict {
+    /// restore under a free name next to the existing item
+    #[default]
+    Rename,
+    /// move the existing item to the trash, then restore
+    Overwrite,
+    /// leave the item in the trash
+    Skip,
+}
+
+#[derive(Default)]
+pub struct RestoreOptions {
+    pub conflict: RestoreConflict,
+    /// folder to restore into instead of the one the item was deleted from
+    pub destination: Option<PitouFilePath>,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub enum RestoreOutcome {
+    Restored,
+    /// left in the trash because of a conflict
+    Skipped,
+    Failed(String),
+}
+
+/// The result of restoring one trash item.
+pub struct RestoreResult {
+    /// id of the trash item
+    pub id: String,
+    pub outcome: RestoreOutcome,
+    /// where the item was restored to
+    pub restored_to: Option<PitouFilePath>,
+}
+
+/// Something that keeps a drive from being unmounted.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DriveBlocker {
+    Transfer(TransferSessionID),
+    Deletion(TransferSessionID),
+    /// a process with files or its working folder on the drive
+    Process {
+        pid: u32,
+        name: String,
+        open_files: u32,
+    },
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum EjectOutcome {
+    /// unmounted and powered off, safe to unplug
+    Ejected,
+    /// unmounted, but the device could not be powered off
+    Unmounted,
+    /// nothing was done because the drive is in use
+    Blocked(Vec<DriveBlocker>),
+    Failed(String),
+}
+
+/// An item of a disk usage tree, with everything inside it added up.
+#[derive(Clone, Serialize, Deserialize, Default)]
+pub struct UsageNode {
+    pub name: String,
+    pub is_dir: bool,
+    /// space taken on disk, which for sparse or compressed files is less than their length
+    pub bytes: u64,
+    pub files: u64,
+    pub dirs: u64,
+    /// the largest items inside, largest first
+    pub children: Vec<UsageNode>,
+    /// bytes of the items left out of `children`
+    pub other_bytes: u64,
+    pub other_items: u64,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct UsageOptions {
+    /// levels of folders below the root whose children are kept in the tree; deeper ones are only added up
+    pub max_depth: u32,
+    /// children kept per folder, the rest being summed in `other_bytes`
+    pub max_children: u32,
+}
+
+impl Default for UsageOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: 8,
+            max_children: 64,
+        }
+    }
+}
+
+/// The state of a scan that only reads, without touching what it scans.
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub enum ScanState {
+    Scanning,
+    Completed,
+    Cancelled,
+}
+
+/// The state of a disk usage scan.
+#[derive(Serialize, Deserialize)]
+pub struct UsageMsg {
+    pub id: TransferSessionID,
+    pub state: ScanState,
+    pub time_elapsed: Duration,
+    pub bytes: u64,
+    pub files: u64,
+    pub dirs: u64,
+    /// what the scan should add up to when that is known, such as the used space of a drive
+    pub expected_bytes: Option<u64>,
+    /// folders that could not be read
+    pub errors: u64,
+    /// set once the scan has completed
+    pub tree: Option<UsageNode>,
+}
+
+/// Files found to have the same content.
+pub struct DuplicateGroup {
+    /// length of each of the files
+    pub size: u64,
+    /// newest first
+    pub files: Vec<PitouFile>,
+}
+
+/// The state of a duplicate scan. Groups are handed out as they are confirmed, each read taking those confirmed since
+/// the previous one.
+pub struct DuplicateMsg {
+    pub id: TransferSessionID,
+    pub state: ScanState,
+    pub time_elapsed: Duration,
+    /// files looked at so far
+    pub files: u64,
+    pub bytes_hashed: u64,
+    /// groups confirmed so far, including those already read
+    pub groups_found: u64,
+    /// space that would be freed by keeping one file of each group found
+    pub wasted_bytes: u64,
+    /// files that could not be read
+    pub errors: u64,
+    pub groups: Vec<DuplicateGroup>,
+}
+
+/// What a report over the files of a folder looks for, to help clean it up.
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub enum ReportKind {
+    /// that many of the largest files, largest first
+    Largest(u32),
+    /// files not modified for that many days, oldest first
+    NotModified(u32),
+    /// files not read for that many days, oldest first. Many systems update access times once a day at most, or never
+    NotAccessed(u32),
+    EmptyFiles,
+    /// folders holding nothing but other empty folders. Only the outermost of them are reported
+    EmptyDirs,
+}
+
+/// How files present on both sides of a comparison are told apart.
+#[derive(Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, Debug)]
+pub enum CompareMethod {
+    /// files of the same length modified at the same time are taken to be identical
+    #[default]
+    SizeAndTime,
+    /// files are identical if their contents hash the same, which reads all of them
+    Content,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub enum CompareStatus {
+    LeftOnly,
+    RightOnly,
+    NewerOnLeft,
+    NewerOnRight,
+    Identical,
+    /// modified at the same time but different all the same, so that neither side can be said to be newer
+    Differs,
+    /// a folder on one side and something else on the other
+    KindMismatch,
+}
+
+/// An item of either folder being compared. Folders found on both sides are not listed themselves, only what they
+/// hold.
+pub struct CompareEntry {
+    pub status: CompareStatus,
+    pub left: Option<PitouFile>,
+    pub right: Option<PitouFile>,
+}
+
+/// How a sync brings the right folder in line with the left one.
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub enum SyncMode {
+    /// make the right folder a copy of the left one, moving what it has more to the trash
+    Mirror,
+    /// copy what is missing or newer on the left, deleting nothing
+    Update,
+    /// copy what is missing or newer on either side to the other
+    TwoWay,
+}
+
+/// What a sync has set going.
+pub struct SyncStarted {
+    /// the transfer session copying files, if there is anything to copy
+    pub transfer: Option<TransferSessionID>,
+    /// the delete session moving extra items of the right folder to the trash, for mirrors
+    pub deletion: Option<TransferSessionID>,
+    /// entries the sync left alone because it could not tell which side to keep
+    pub conflicts: Vec<CompareEntry>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveFormat {
+    /// The extension archives of this format end with, without its leading dot.
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+            Self::TarZst => "tar.zst",
+        }
+    }
+}
+
+/// How hard an archive is compressed, traded against the time it takes. Plain tar archives are not compressed at all.
+#[derive(Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, Debug)]
+pub enum CompressionLevel {
+    Fastest,
+    #[default]
+    Balanced,
+    Smallest,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct CompressOptions {
+    pub format: ArchiveFormat,
+    pub level: CompressionLevel,
+}
+
+/// What to do when an extracted item would land on an existing one. The choice is made for each item at the top of the
+/// archive, and applies to whatever the archive holds under it.
+#[derive(Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, Debug)]
+pub enum ExtractConflict {
+    /// extract under a free name next to the existing item
+    #[default]
+    Rename,
+    /// extract into existing folders, replacing the files in the way
+    Overwrite,
+    /// leave the existing item alone and extract nothing under it
+    Skip,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default)]
+pub struct ExtractOptions {
+    pub conflict: ExtractConflict,
+    /// the password of an encrypted zip archive, by the traditional scheme or AES
+    pub password: Option<String>,
+}
+
+/// The state of an archive session.
+pub enum ArchiveMsg {
+    /// Progress is measured in bytes of the files being packed, like transfers.
+    Compress {
+        id: TransferSessionID,
+        state: TransferState,
+        time_elapsed: Duration,
+        progress: TransferProgress,
+        /// the archive being written, which only takes this name once it is complete
+        archive: PitouFilePath,
+        /// items that could not be packed
+        errors: Vec<TransferError>,
+        /// set once the session has terminated
+        outcome: Option<TransferOutcome>,
+    },
+    /// Progress is measured in bytes of the files being extracted.
+    Extract {
+        id: TransferSessionID,
+        state: TransferState,
+        time_elapsed: Duration,
+        progress: TransferProgress,
+        archive: PitouFilePath,
+        /// the folder the items of the archive go to
+        destination: PitouFilePath,
+        /// entries that could not be extracted, named as they would have been
+        errors: Vec<TransferError>,
+        /// set once the session has terminated
+        outcome: Option<TransferOutcome>,
+    },
+}
+
+impl ArchiveMsg {
+    pub fn id(&self) -> TransferSessionID {
+        match self {
+            Self::Compress { id, .. } | Self::Extract { id, .. } => *id,
+        }
+    }
+
+    pub fn details(self) -> (TransferState, Duration) {
+        match self {
+            Self::Compress {
+                state,
+                time_elapsed,
+                ..
+            }
+            | Self::Extract {
+                state,
+                time_elapsed,
+                ..
+            } => (state, time_elapsed),
+        }
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        match self {
+            Self::Compress { state, .. } | Self::Extract { state, .. } => state.is_terminted(),
+        }
+    }
+
+    pub fn progress(&self) -> &TransferProgress {
+        match self {
+            Self::Compress { progress, .. } | Self::Extract { progress, .. } => progress,
+        }
+    }
+
+    pub fn errors(&self) -> &[TransferError] {
+        match self {
+            Self::Compress { errors, .. } | Self::Extract { errors, .. } => errors,
+        }
+    }
+
+    pub fn outcome(&self) -> Option<TransferOutcome> {
+        match self {
+            Self::Compress { outcome, .. } | Self::Extract { outcome, .. } => *outcome,
+        }
+    }
+}